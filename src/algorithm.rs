@@ -0,0 +1,326 @@
+//! Signature algorithms, identified by the prefix of key IDs such as
+//! `ed25519:auto`.
+//!
+//! The key and signature types elsewhere in the crate are ed25519 specific;
+//! an `Algorithms` registry allows verifying signatures made with other
+//! algorithms given the raw key and signature bytes.
+//!
+//! Key ID parsing is part of the `canonical` layer, the rest needs the
+//! `signing` feature.
+
+use std::fmt;
+
+#[cfg(feature = "signing")]
+use sodiumoxide::crypto::sign;
+
+#[cfg(feature = "signing")]
+use keys::{VerifyResult, check_public_key};
+#[cfg(feature = "signing")]
+use signed::{AsCanonical, Signed};
+
+
+pub trait Algorithm {
+    /// The name used as the key ID prefix, e.g. `ed25519`.
+    fn name(&self) -> &'static str;
+
+    fn verify(&self, public_key: &[u8], sig: &[u8], message: &[u8]) -> bool;
+}
+
+
+#[cfg(feature = "signing")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ed25519;
+
+#[cfg(feature = "signing")]
+impl Algorithm for Ed25519 {
+    fn name(&self) -> &'static str {
+        "ed25519"
+    }
+
+    /// Small-order and non-canonical keys never verify, as with `VerifyKey`.
+    fn verify(&self, public_key: &[u8], sig: &[u8], message: &[u8]) -> bool {
+        match (sign::PublicKey::from_slice(public_key), sign::Signature::from_slice(sig)) {
+            (Some(public_key), Some(sig)) => {
+                check_public_key(&public_key.0).is_ok() &&
+                sign::verify_detached(&sig, message, &public_key)
+            }
+            _ => false,
+        }
+    }
+}
+
+
+/// Separates the namespace of a structured key ID version from its label,
+/// e.g. `service1__2024`. Versions may only contain letters, digits and
+/// underscores, and a single underscore is common in unstructured ones, such
+/// as Synapse's `a_XXXX`, so it's doubled.
+pub const NAMESPACE_DELIMITER: &'static str = "__";
+
+
+/// A key ID split into its algorithm and version, e.g. `ed25519` and `auto`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyId<'a> {
+    pub algorithm: &'a str,
+    pub version: &'a str,
+}
+
+impl<'a> KeyId<'a> {
+    /// Split the key ID on its first colon. Returns `None` if there isn't one
+    /// or the algorithm is empty.
+    pub fn parse(key_id: &'a str) -> Option<KeyId<'a>> {
+        key_id.find(':').and_then(|idx| {
+            if idx == 0 {
+                None
+            } else {
+                Some(KeyId {
+                    algorithm: &key_id[..idx],
+                    version: &key_id[idx + 1..],
+                })
+            }
+        })
+    }
+
+    /// The namespace of a structured version such as `service1__2024`, i.e.
+    /// the part before the first `NAMESPACE_DELIMITER`.
+    pub fn namespace(&self) -> Option<&'a str> {
+        self.version.find(NAMESPACE_DELIMITER).map(|idx| &self.version[..idx])
+    }
+
+    /// The version without its namespace, e.g. `2024` for `service1__2024`.
+    /// This is the whole version if it isn't namespaced.
+    pub fn label(&self) -> &'a str {
+        match self.version.find(NAMESPACE_DELIMITER) {
+            Some(idx) => &self.version[idx + NAMESPACE_DELIMITER.len()..],
+            None => self.version,
+        }
+    }
+}
+
+impl<'a> fmt::Display for KeyId<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, self.version)
+    }
+}
+
+/// Build a namespaced key ID such as `ed25519:service1__2024`. Returns `None`
+/// if the namespace is empty or contains an underscore or colon, as it then
+/// wouldn't parse back out.
+pub fn namespaced_key_id(algorithm: &str, namespace: &str, label: &str) -> Option<String> {
+    if namespace.is_empty() || namespace.contains('_') || namespace.contains(':') {
+        None
+    } else {
+        Some(format!("{}:{}{}{}", algorithm, namespace, NAMESPACE_DELIMITER, label))
+    }
+}
+
+/// The namespace of a key ID, if it has a structured version.
+pub fn key_id_namespace(key_id: &str) -> Option<&str> {
+    KeyId::parse(key_id).and_then(|key_id| key_id.namespace())
+}
+
+/// Whether the key ID is for an algorithm the crate's own key types support,
+/// i.e. ed25519.
+#[cfg(feature = "signing")]
+pub fn is_supported_key_id(key_id: &str) -> bool {
+    KeyId::parse(key_id).map_or(false, |key_id| key_id.algorithm == Ed25519.name())
+}
+
+
+/// A set of algorithms to dispatch verification to by key ID.
+#[cfg(feature = "signing")]
+pub struct Algorithms {
+    algorithms: Vec<Box<Algorithm>>,
+}
+
+#[cfg(feature = "signing")]
+impl Algorithms {
+    /// An empty registry.
+    pub fn new() -> Algorithms {
+        Algorithms { algorithms: Vec::new() }
+    }
+
+    /// Add an algorithm, replacing any existing one with the same name.
+    pub fn register<A>(&mut self, algorithm: A)
+        where A: Algorithm + 'static
+    {
+        self.algorithms.retain(|a| a.name() != algorithm.name());
+        self.algorithms.push(Box::new(algorithm));
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Algorithm> {
+        self.algorithms.iter().find(|a| a.name() == name).map(|a| &**a)
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.algorithms.iter().map(|a| a.name()).collect()
+    }
+
+    /// Verify a signature made by the key with the given key ID, dispatching
+    /// on the key ID's algorithm.
+    pub fn verify(&self,
+                  key_id: &str,
+                  public_key: &[u8],
+                  sig: &[u8],
+                  message: &[u8])
+                  -> VerifyResult {
+        let algorithm = KeyId::parse(key_id).and_then(|key_id| self.get(key_id.algorithm));
+        match algorithm {
+            Some(algorithm) if algorithm.verify(public_key, sig, message) => VerifyResult::Valid,
+            Some(_) => VerifyResult::Invalid,
+            None => VerifyResult::UnsupportedAlgorithm,
+        }
+    }
+
+    /// Verify the object's signature by the given entity and key ID, which may
+    /// have been made with any registered algorithm.
+    pub fn verify_signed<T>(&self,
+                            obj: &T,
+                            entity: &str,
+                            key_id: &str,
+                            public_key: &[u8])
+                            -> VerifyResult
+        where T: AsCanonical + Signed
+    {
+        match obj.signatures().get_raw_signature(entity, key_id) {
+            Some(sig) => self.verify(key_id, public_key, sig, &obj.as_canonical()),
+            None => VerifyResult::Unsigned,
+        }
+    }
+}
+
+#[cfg(feature = "signing")]
+impl Default for Algorithms {
+    /// A registry of the algorithms supported by the crate, i.e. ed25519.
+    fn default() -> Algorithms {
+        let mut algorithms = Algorithms::new();
+        algorithms.register(Ed25519);
+        algorithms
+    }
+}
+
+
+#[cfg(all(test, feature = "signing"))]
+mod tests {
+    use super::*;
+    use frozen::FrozenStruct;
+    use keys::{NamedPublicKey, NamedSecretKey, SecretKey, SigningKeyPair, VerifyKey,
+               VerifyResult};
+    use serde_json::Value;
+    use signed::{AsCanonical, SignedMut, SimpleSigned};
+    use std::borrow::Cow;
+
+    struct Reversed;
+
+    impl Algorithm for Reversed {
+        fn name(&self) -> &'static str {
+            "reversed"
+        }
+
+        fn verify(&self, _public_key: &[u8], sig: &[u8], message: &[u8]) -> bool {
+            sig.iter().rev().eq(message.iter())
+        }
+    }
+
+    struct Message(&'static [u8]);
+
+    impl AsCanonical for Message {
+        fn as_canonical(&self) -> Cow<[u8]> {
+            Cow::Borrowed(self.0)
+        }
+    }
+
+    #[test]
+    fn parse_key_id() {
+        let key_id = KeyId::parse("ed25519:a:b").unwrap();
+        assert_eq!(key_id.algorithm, "ed25519");
+        assert_eq!(key_id.version, "a:b");
+        assert_eq!(key_id.to_string(), "ed25519:a:b");
+
+        assert_eq!(KeyId::parse("ed25519"), None);
+        assert_eq!(KeyId::parse(":auto"), None);
+
+        assert_eq!(key_id.namespace(), None);
+        assert_eq!(key_id.label(), "a:b");
+
+        assert!(is_supported_key_id("ed25519:auto"));
+        assert!(!is_supported_key_id("curve25519:auto"));
+    }
+
+    #[test]
+    fn namespaces() {
+        let key_id = KeyId::parse("ed25519:service1__2024_b").unwrap();
+        assert_eq!(key_id.namespace(), Some("service1"));
+        assert_eq!(key_id.label(), "2024_b");
+
+        assert_eq!(namespaced_key_id("ed25519", "service1", "2024").unwrap(),
+                   "ed25519:service1__2024");
+        assert_eq!(namespaced_key_id("ed25519", "service_1", "2024"), None);
+        assert_eq!(namespaced_key_id("ed25519", "", "2024"), None);
+
+        assert_eq!(key_id_namespace("ed25519:service1__2024"), Some("service1"));
+        assert_eq!(key_id_namespace("ed25519:auto"), None);
+        assert_eq!(key_id_namespace("service1__2024"), None);
+
+        // Synapse's generated key IDs aren't namespaced.
+        let key_id = KeyId::parse("ed25519:a_HDhS").unwrap();
+        assert_eq!(key_id.namespace(), None);
+        assert_eq!(key_id.label(), "a_HDhS");
+    }
+
+    #[test]
+    fn dispatch() {
+        let mut algorithms = Algorithms::default();
+        algorithms.register(Reversed);
+        assert_eq!(algorithms.names(), vec!["ed25519", "reversed"]);
+
+        let key = SigningKeyPair::generate("domain", "ed25519:1");
+        let sig = key.sign_detached(&Message(b"abc"));
+
+        assert_eq!(algorithms.verify("ed25519:1", &key.public.0, &sig.0, b"abc"),
+                   VerifyResult::Valid);
+        assert_eq!(algorithms.verify("ed25519:1", &key.public.0, &sig.0, b"abd"),
+                   VerifyResult::Invalid);
+        assert_eq!(algorithms.verify("reversed:1", b"", b"cba", b"abc"),
+                   VerifyResult::Valid);
+        assert_eq!(algorithms.verify("reversed:1", b"", b"abc", b"abc"),
+                   VerifyResult::Invalid);
+        assert_eq!(algorithms.verify("rsa:1", b"", b"", b"abc"),
+                   VerifyResult::UnsupportedAlgorithm);
+        assert_eq!(algorithms.verify("auto", b"", b"", b"abc"),
+                   VerifyResult::UnsupportedAlgorithm);
+
+        // With the identity as both key and R, and S zero, the signature holds
+        // for any message, so the key has to be rejected up front.
+        let mut identity = [0; 64];
+        identity[0] = 1;
+        assert_eq!(algorithms.verify("ed25519:1", &identity[..32], &identity, b"abc"),
+                   VerifyResult::Invalid);
+    }
+
+    #[test]
+    fn verify_signed() {
+        let mut algorithms = Algorithms::default();
+        algorithms.register(Reversed);
+
+        let key = SigningKeyPair::generate("domain", "ed25519:1");
+        let mut frozen: FrozenStruct<SimpleSigned, Value> =
+            FrozenStruct::from_slice(br#"{"a":1}"#).unwrap();
+        key.sign(&mut frozen);
+        let reversed: Vec<u8> = frozen.as_canonical().iter().rev().cloned().collect();
+        frozen.signatures_mut().add_raw_signature("domain", "reversed:1", &reversed).unwrap();
+
+        assert_eq!(algorithms.verify_signed(&frozen, "domain", "ed25519:1", &key.public.0),
+                   VerifyResult::Valid);
+        assert_eq!(algorithms.verify_signed(&frozen, "domain", "reversed:1", b""),
+                   VerifyResult::Valid);
+        assert_eq!(algorithms.verify_signed(&frozen, "domain", "reversed:2", b""),
+                   VerifyResult::Unsigned);
+        assert_eq!(Algorithms::default().verify_signed(&frozen, "domain", "reversed:1", b""),
+                   VerifyResult::UnsupportedAlgorithm);
+
+        // The crate's own key types only support ed25519 signatures.
+        let mut verify_key = VerifyKey::from_signing_key(&key);
+        verify_key.key_id = "reversed:1".to_string();
+        assert_eq!(verify_key.verify(&frozen), VerifyResult::UnsupportedAlgorithm);
+    }
+}
@@ -0,0 +1,314 @@
+//! Re-verifying archived signed objects against the keys that were valid when
+//! each object was made, for periodic integrity audits.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use sodiumoxide::crypto::sign;
+
+use keys::{PublicKey, ServerKeys, VerifyKey};
+use signed::{AsCanonical, Signed};
+
+
+/// The result of looking up a key as of a given time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyLookup<'a> {
+    Valid(&'a VerifyKey),
+    /// The key is known but had expired by then.
+    Expired(&'a VerifyKey),
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveError {
+    /// A different key is already known under the key's entity and key ID.
+    ConflictingKey(VerifyKey),
+}
+
+impl fmt::Display for ArchiveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ArchiveError::ConflictingKey(ref key) => {
+                write!(f,
+                       "A different key is already known as {} {}",
+                       key.entity,
+                       key.key_id)
+            }
+        }
+    }
+}
+
+
+/// A key store that knows when each key was valid.
+pub trait HistoricalKeyStore {
+    fn get_key_at(&self, entity: &str, key_id: &str, ts: u64) -> KeyLookup;
+}
+
+
+/// A `HistoricalKeyStore` that holds its keys in memory, each with the time up
+/// to which it was valid.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryHistoricalKeyStore {
+    keys: BTreeMap<String, BTreeMap<String, (VerifyKey, u64)>>,
+}
+
+impl MemoryHistoricalKeyStore {
+    pub fn new() -> MemoryHistoricalKeyStore {
+        MemoryHistoricalKeyStore::default()
+    }
+
+    /// Add a key that was valid up to `valid_until_ts`. If the key is already
+    /// known the later of the two times is kept.
+    ///
+    /// Fails, without adding it, if a different key is known under the same
+    /// entity and key ID, as there'd be no telling which of them an archived
+    /// signature was made with.
+    pub fn add_key(&mut self, key: VerifyKey, valid_until_ts: u64) -> Result<(), ArchiveError> {
+        try!(self.check_conflict(&key));
+        self.insert_key(key, valid_until_ts);
+        Ok(())
+    }
+
+    /// Add the keys from a server key response: current keys up to its
+    /// `valid_until_ts`, and old keys up to when they expired. Fails, without
+    /// adding any, if any conflicts with a known key, see `add_key`.
+    pub fn add_server_keys(&mut self, keys: &ServerKeys) -> Result<(), ArchiveError> {
+        for key in keys.verify_keys.iter().chain(keys.old_verify_keys.iter().map(|k| &k.0)) {
+            try!(self.check_conflict(key));
+        }
+
+        for key in &keys.verify_keys {
+            self.insert_key(key.clone(), keys.valid_until_ts);
+        }
+        for &(ref key, expired_ts) in &keys.old_verify_keys {
+            // Old keys are valid for objects made strictly before they expired.
+            self.insert_key(key.clone(), expired_ts.saturating_sub(1));
+        }
+        Ok(())
+    }
+
+    fn check_conflict(&self, key: &VerifyKey) -> Result<(), ArchiveError> {
+        match self.keys.get(&key.entity).and_then(|keys| keys.get(&key.key_id)) {
+            Some(&(ref known, _)) if known != key => Err(ArchiveError::ConflictingKey(key.clone())),
+            _ => Ok(()),
+        }
+    }
+
+    fn insert_key(&mut self, key: VerifyKey, valid_until_ts: u64) {
+        let entry = self.keys
+                        .entry(key.entity.clone())
+                        .or_insert_with(BTreeMap::new)
+                        .entry(key.key_id.clone())
+                        .or_insert_with(|| (key.clone(), valid_until_ts));
+        if entry.1 < valid_until_ts {
+            *entry = (key, valid_until_ts);
+        }
+    }
+}
+
+impl HistoricalKeyStore for MemoryHistoricalKeyStore {
+    fn get_key_at(&self, entity: &str, key_id: &str, ts: u64) -> KeyLookup {
+        match self.keys.get(entity).and_then(|keys| keys.get(key_id)) {
+            Some(&(ref key, valid_until_ts)) if ts <= valid_until_ts => KeyLookup::Valid(key),
+            Some(&(ref key, _)) => KeyLookup::Expired(key),
+            None => KeyLookup::Unknown,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AuditOutcome {
+    Valid,
+    /// The signature verifies but the key had expired when the object was
+    /// made.
+    ExpiredKey,
+    Invalid,
+    UnknownKey,
+}
+
+/// A signature that didn't check out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditSample {
+    /// The position of the object in the audited archive.
+    pub index: usize,
+    pub entity: String,
+    pub key_id: String,
+    pub outcome: AuditOutcome,
+}
+
+/// Counts of each outcome over every signature in an archive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuditReport {
+    pub objects: usize,
+    pub valid: usize,
+    pub expired_key: usize,
+    pub invalid: usize,
+    pub unknown_key: usize,
+    /// Up to the requested number of samples of each failing outcome.
+    pub samples: Vec<AuditSample>,
+}
+
+impl AuditReport {
+    /// Whether every signature verified with a key valid at the time.
+    pub fn is_clean(&self) -> bool {
+        self.expired_key == 0 && self.invalid == 0 && self.unknown_key == 0
+    }
+
+    fn count(&self, outcome: AuditOutcome) -> usize {
+        match outcome {
+            AuditOutcome::Valid => self.valid,
+            AuditOutcome::ExpiredKey => self.expired_key,
+            AuditOutcome::Invalid => self.invalid,
+            AuditOutcome::UnknownKey => self.unknown_key,
+        }
+    }
+
+    fn record(&mut self,
+              index: usize,
+              entity: &str,
+              key_id: &str,
+              outcome: AuditOutcome,
+              max_samples: usize) {
+        if outcome != AuditOutcome::Valid && self.count(outcome) < max_samples {
+            self.samples.push(AuditSample {
+                index: index,
+                entity: entity.to_string(),
+                key_id: key_id.to_string(),
+                outcome: outcome,
+            });
+        }
+        match outcome {
+            AuditOutcome::Valid => self.valid += 1,
+            AuditOutcome::ExpiredKey => self.expired_key += 1,
+            AuditOutcome::Invalid => self.invalid += 1,
+            AuditOutcome::UnknownKey => self.unknown_key += 1,
+        }
+    }
+}
+
+
+/// Re-check every signature on every object, each given with the time it was
+/// made, against the keys valid at that time.
+pub fn audit_archive<I, T, S>(objects: I, store: &S, max_samples: usize) -> AuditReport
+    where I: IntoIterator<Item = (u64, T)>,
+          T: AsCanonical + Signed,
+          S: HistoricalKeyStore
+{
+    let mut report = AuditReport::default();
+
+    for (index, (ts, obj)) in objects.into_iter().enumerate() {
+        report.objects += 1;
+        let canonical = obj.as_canonical();
+
+        for (entity, sigs) in obj.signatures().iter() {
+            for (key_id, sig) in sigs {
+                let outcome = match store.get_key_at(entity, key_id, ts) {
+                    KeyLookup::Unknown => AuditOutcome::UnknownKey,
+                    KeyLookup::Valid(key) | KeyLookup::Expired(key)
+                        if !sign::verify_detached(sig, &canonical, key.public_key()) => {
+                        AuditOutcome::Invalid
+                    }
+                    KeyLookup::Valid(_) => AuditOutcome::Valid,
+                    KeyLookup::Expired(_) => AuditOutcome::ExpiredKey,
+                };
+                report.record(index, entity, key_id, outcome, max_samples);
+            }
+        }
+    }
+
+    report
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{KeyRing, NamedSecretKey, ServerKeys, SigningKeyPair, VerifyKey};
+    use signed::{Signed, SignedMut};
+    use value::SignedValue;
+
+    fn signed(content: &[u8], keys: &[&SigningKeyPair]) -> SignedValue {
+        let mut value = SignedValue::from_slice(content).unwrap();
+        for key in keys {
+            key.sign(&mut value);
+        }
+        value
+    }
+
+    #[test]
+    fn audit() {
+        let old = SigningKeyPair::generate("example.com", "ed25519:a");
+        let current = SigningKeyPair::generate("example.com", "ed25519:b");
+        let stranger = SigningKeyPair::generate("example.org", "ed25519:a");
+
+        let mut ring = KeyRing::new("example.com");
+        ring.add_signing_key(old.clone()).unwrap();
+        ring.add_signing_key(current.clone()).unwrap();
+        ring.retire_key("ed25519:a", 1000);
+
+        let mut store = MemoryHistoricalKeyStore::new();
+        store.add_server_keys(&ring.server_keys(5000)).unwrap();
+
+        let mut tampered = signed(br#"{"a":4}"#, &[&current]);
+        *tampered.signatures_mut() = signed(br#"{"a":5}"#, &[&current]).signatures().clone();
+
+        let archive = vec![(500, signed(br#"{"a":1}"#, &[&old, &current])),
+                           (2000, signed(br#"{"a":2}"#, &[&old])),
+                           (2000, signed(br#"{"a":3}"#, &[&current, &stranger])),
+                           (6000, tampered)];
+
+        let report = audit_archive(archive, &store, 1);
+        assert_eq!(report.objects, 4);
+        assert_eq!(report.valid, 3);
+        assert_eq!(report.expired_key, 1);
+        assert_eq!(report.unknown_key, 1);
+        assert_eq!(report.invalid, 1);
+        assert!(!report.is_clean());
+
+        assert_eq!(report.samples,
+                   vec![AuditSample {
+                            index: 1,
+                            entity: "example.com".to_string(),
+                            key_id: "ed25519:a".to_string(),
+                            outcome: AuditOutcome::ExpiredKey,
+                        },
+                        AuditSample {
+                            index: 2,
+                            entity: "example.org".to_string(),
+                            key_id: "ed25519:a".to_string(),
+                            outcome: AuditOutcome::UnknownKey,
+                        },
+                        AuditSample {
+                            index: 3,
+                            entity: "example.com".to_string(),
+                            key_id: "ed25519:b".to_string(),
+                            outcome: AuditOutcome::Invalid,
+                        }]);
+    }
+
+    #[test]
+    fn conflicting_keys() {
+        let key = VerifyKey::from_signing_key(&SigningKeyPair::generate("example.com",
+                                                                        "ed25519:a"));
+        let other = VerifyKey::from_signing_key(&SigningKeyPair::generate("example.com",
+                                                                          "ed25519:a"));
+
+        let mut store = MemoryHistoricalKeyStore::new();
+        store.add_key(key.clone(), 1000).unwrap();
+        store.add_key(key.clone(), 2000).unwrap();
+        assert_eq!(store.add_key(other.clone(), 3000),
+                   Err(ArchiveError::ConflictingKey(other.clone())));
+        assert_eq!(store.get_key_at("example.com", "ed25519:a", 2000),
+                   KeyLookup::Valid(&key));
+
+        // A response with a conflicting key adds none of its keys.
+        let mut keys = ServerKeys::new("example.com", 5000);
+        keys.verify_keys.push(VerifyKey::from_signing_key(&SigningKeyPair::generate("example.com",
+                                                                                    "ed25519:b")));
+        keys.old_verify_keys.push((other.clone(), 1000));
+        assert_eq!(store.add_server_keys(&keys),
+                   Err(ArchiveError::ConflictingKey(other)));
+        assert_eq!(store.get_key_at("example.com", "ed25519:b", 0), KeyLookup::Unknown);
+    }
+}
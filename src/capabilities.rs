@@ -0,0 +1,138 @@
+//! A description of what this build of the crate can verify, for services to
+//! advertise to peers and to pick a format both sides support.
+
+use serde;
+
+use algorithm::Algorithms;
+use hash::SHA256;
+use ser::CanonicalOptions;
+
+
+/// A named set of canonicalization rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CanonicalProfile {
+    pub name: &'static str,
+    pub options: CanonicalOptions,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The version of the crate.
+    pub version: &'static str,
+    /// The signature algorithms verification dispatches to, by key ID prefix.
+    pub signature_algorithms: Vec<&'static str>,
+    /// The content hash algorithms, by their key in the `hashes` object.
+    pub hash_algorithms: Vec<&'static str>,
+    pub canonical_profiles: Vec<CanonicalProfile>,
+    /// The optional cargo features compiled in.
+    pub features: Vec<&'static str>,
+}
+
+impl Capabilities {
+    pub fn supports_algorithm(&self, name: &str) -> bool {
+        self.signature_algorithms.iter().any(|&a| a == name)
+    }
+
+    pub fn profile(&self, name: &str) -> Option<&CanonicalProfile> {
+        self.canonical_profiles.iter().find(|p| p.name == name)
+    }
+
+    /// The first of `names`, in order of preference, that is supported.
+    pub fn negotiate_algorithm<'a>(&self, names: &[&'a str]) -> Option<&'a str> {
+        names.iter().cloned().find(|name| self.supports_algorithm(name))
+    }
+}
+
+impl serde::Serialize for Capabilities {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        let profiles: Vec<&str> = self.canonical_profiles.iter().map(|p| p.name).collect();
+
+        let mut state = try!(serializer.serialize_struct("Capabilities", 5));
+        try!(serializer.serialize_struct_elt(&mut state, "version", self.version));
+        try!(serializer.serialize_struct_elt(&mut state,
+                                             "signature_algorithms",
+                                             &self.signature_algorithms));
+        try!(serializer.serialize_struct_elt(&mut state, "hash_algorithms", &self.hash_algorithms));
+        try!(serializer.serialize_struct_elt(&mut state, "canonical_profiles", &profiles));
+        try!(serializer.serialize_struct_elt(&mut state, "features", &self.features));
+        serializer.serialize_struct_end(state)
+    }
+}
+
+
+/// Describe this build: the algorithms registered by `Algorithms::default`,
+/// the `canonicalize`, `canonicalize_strict` and `CanonicalOptions::jcs`
+/// profiles, and the features it was compiled with.
+pub fn capabilities() -> Capabilities {
+    let mut features = Vec::new();
+    if cfg!(feature = "dyn-signatures") {
+        features.push("dyn-signatures");
+    }
+    if cfg!(feature = "http") {
+        features.push("http");
+    }
+    if cfg!(feature = "keyserver") {
+        features.push("keyserver");
+    }
+    if cfg!(feature = "golden") {
+        features.push("golden");
+    }
+
+    Capabilities {
+        version: env!("CARGO_PKG_VERSION"),
+        signature_algorithms: Algorithms::default().names(),
+        hash_algorithms: vec![SHA256],
+        canonical_profiles: vec![CanonicalProfile {
+                                     name: "matrix",
+                                     options: CanonicalOptions::default(),
+                                 },
+                                 CanonicalProfile {
+                                     name: "matrix-strict",
+                                     options: CanonicalOptions::strict(),
+                                 },
+                                 CanonicalProfile {
+                                     name: "jcs",
+                                     options: CanonicalOptions::jcs(),
+                                 }],
+        features: features,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ser::{CanonicalOptions, canonicalize_with};
+    use serde_json;
+
+    #[test]
+    fn describe() {
+        let caps = capabilities();
+        assert!(caps.supports_algorithm("ed25519"));
+        assert!(!caps.supports_algorithm("rsa"));
+        assert_eq!(caps.negotiate_algorithm(&["rsa", "ed25519"]), Some("ed25519"));
+        assert_eq!(caps.negotiate_algorithm(&["rsa"]), None);
+        assert_eq!(caps.hash_algorithms, vec!["sha256"]);
+        assert_eq!(caps.features.contains(&"keyserver"), cfg!(feature = "keyserver"));
+
+        let strict = caps.profile("matrix-strict").unwrap();
+        assert_eq!(strict.options, CanonicalOptions::strict());
+        assert!(canonicalize_with(br#"{"a":1.5}"#, &strict.options).is_err());
+
+        let jcs = caps.profile("jcs").unwrap();
+        assert_eq!(&canonicalize_with(br#"{"a":1.0}"#, &jcs.options).unwrap()[..],
+                   &br#"{"a":1}"#[..]);
+        assert!(caps.profile("cbor").is_none());
+
+        let json = serde_json::to_value(&caps);
+        assert_eq!(json.find("signature_algorithms"),
+                   Some(&serde_json::to_value(&vec!["ed25519"])));
+        assert_eq!(json.find("canonical_profiles"),
+                   Some(&serde_json::to_value(&vec!["matrix", "matrix-strict", "jcs"])));
+        assert_eq!(json.find("version").and_then(|v| v.as_str()),
+                   Some(env!("CARGO_PKG_VERSION")));
+    }
+}
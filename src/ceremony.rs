@@ -0,0 +1,127 @@
+//! Generating a new signing key so that an operator can compare its
+//! fingerprint out of band before the key is put into use.
+
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sign;
+
+use keys::SigningKeyPair;
+
+
+/// Number of digit groups in a verification code.
+const CODE_GROUPS: usize = 6;
+/// Number of bytes of the fingerprint used for each digit group.
+const CODE_GROUP_BYTES: usize = 5;
+
+
+/// Render a public key as a short code of grouped decimal digits, e.g.
+/// `"01234 56789 ..."`, suitable for reading aloud or comparing by eye.
+///
+/// The code is derived from the SHA-256 of the public key, so both sides of an
+/// onboarding can compute it independently.
+pub fn verification_code(public: &sign::PublicKey) -> String {
+    let digest = sha256::hash(&public.0[..]);
+
+    let groups: Vec<String> = digest.0
+                                    .chunks(CODE_GROUP_BYTES)
+                                    .take(CODE_GROUPS)
+                                    .map(|chunk| {
+                                        let num = chunk.iter()
+                                                       .fold(0u64, |acc, b| (acc << 8) | *b as u64);
+                                        format!("{:05}", num % 100000)
+                                    })
+                                    .collect();
+
+    groups.join(" ")
+}
+
+
+/// A freshly generated signing key that has not yet been confirmed by the
+/// operator.
+#[derive(Debug)]
+pub struct Ceremony {
+    key: SigningKeyPair,
+}
+
+impl Ceremony {
+    /// Generate a new signing key for the ceremony.
+    pub fn new<E, K>(entity: E, key_id: K) -> Ceremony
+        where E: Into<String>,
+              K: Into<String>
+    {
+        Ceremony::from_key(SigningKeyPair::generate(entity, key_id))
+    }
+
+    /// Run the ceremony for an existing signing key.
+    pub fn from_key(key: SigningKeyPair) -> Ceremony {
+        Ceremony { key: key }
+    }
+
+    pub fn entity(&self) -> &str {
+        &self.key.entity
+    }
+
+    pub fn key_id(&self) -> &str {
+        &self.key.key_id
+    }
+
+    /// Return a unpadded base64 version of the public key.
+    pub fn public_key_b64(&self) -> String {
+        self.key.public_key_b64()
+    }
+
+    /// Return the human comparable verification code of the public key.
+    pub fn verification_code(&self) -> String {
+        verification_code(&self.key.public)
+    }
+
+    /// Complete the ceremony if the code entered by the operator matches the
+    /// verification code, ignoring whitespace. The signing key is only handed
+    /// out, and so can only be persisted, once confirmed.
+    pub fn confirm(self, code: &str) -> Result<SigningKeyPair, Ceremony> {
+        let entered = code.chars().filter(|c| !c.is_whitespace());
+        let expected = self.verification_code();
+
+        if entered.eq(expected.chars().filter(|c| !c.is_whitespace())) {
+            Ok(self.key)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{PublicKey, SigningKeyPair, VerifyKey};
+    use rustc_serialize::base64::FromBase64;
+
+    #[test]
+    fn code() {
+        let seed = "YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA1".from_base64().unwrap();
+        let sig_key = SigningKeyPair::from_seed(&seed, "domain", "ed25519:1").unwrap();
+        let verify_key = VerifyKey::from_signing_key(&sig_key);
+
+        let ceremony = Ceremony::from_key(sig_key);
+        let code = ceremony.verification_code();
+
+        assert_eq!(code.len(), 6 * 5 + 5);
+        assert!(code.split(' ').all(|g| g.len() == 5 && g.chars().all(|c| c.is_digit(10))));
+        assert_eq!(code, verification_code(verify_key.public_key()));
+        // Fixed regardless of the platform's byte order.
+        assert_eq!(code, "71292 06253 53010 72325 43204 31835");
+        assert_eq!(ceremony.public_key_b64(), verify_key.public_key_b64());
+    }
+
+    #[test]
+    fn confirm() {
+        let ceremony = Ceremony::new("domain", "ed25519:1");
+        let code = ceremony.verification_code();
+
+        let ceremony = ceremony.confirm("00000 00000").err().expect("Confirmed wrong code");
+
+        let key = ceremony.confirm(&code.replace(" ", "")).unwrap();
+        assert_eq!(key.entity, "domain");
+        assert_eq!(key.key_id, "ed25519:1");
+    }
+}
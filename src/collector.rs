@@ -0,0 +1,173 @@
+//! Gathering signatures on an object from several parties, e.g. when each
+//! signer has to be contacted separately.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sign;
+
+use keys::{PublicKey, VerifyKey};
+use ser::signatures::SignatureMap;
+use signed::{AsCanonical, SignedMut};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CollectError {
+    /// The signature isn't from one of the expected signers.
+    UnexpectedSigner,
+    /// The signature didn't verify against the signer's key.
+    InvalidSignature,
+}
+
+
+/// Collects detached signatures over the canonical form of an object from a
+/// set of expected signers, checking each as it arrives.
+#[derive(Debug, Clone)]
+pub struct SignatureCollector {
+    canonical: Vec<u8>,
+    hash: sha256::Digest,
+    expected: BTreeMap<(String, String), VerifyKey>,
+    signatures: SignatureMap,
+}
+
+impl SignatureCollector {
+    pub fn new<T, I>(obj: &T, signers: I) -> SignatureCollector
+        where T: AsCanonical,
+              I: IntoIterator<Item = VerifyKey>
+    {
+        let canonical = obj.as_canonical().into_owned();
+        SignatureCollector {
+            hash: sha256::hash(&canonical),
+            canonical: canonical,
+            expected: signers.into_iter()
+                             .map(|key| ((key.entity.clone(), key.key_id.clone()), key))
+                             .collect(),
+            signatures: SignatureMap::new(),
+        }
+    }
+
+    /// The SHA-256 of the canonical form that is being signed.
+    pub fn canonical_hash(&self) -> &sha256::Digest {
+        &self.hash
+    }
+
+    /// Add a signature from one of the expected signers.
+    pub fn add_signature(&mut self,
+                         entity: &str,
+                         key_id: &str,
+                         sig: sign::Signature)
+                         -> Result<(), CollectError> {
+        let valid = {
+            let key = try!(self.expected
+                               .get(&(entity.to_string(), key_id.to_string()))
+                               .ok_or(CollectError::UnexpectedSigner));
+            sign::verify_detached(&sig, &self.canonical, key.public_key())
+        };
+
+        if valid {
+            self.signatures.add_signature(entity, key_id, sig);
+            Ok(())
+        } else {
+            Err(CollectError::InvalidSignature)
+        }
+    }
+
+    /// Whether every expected signer has provided a valid signature.
+    pub fn is_complete(&self) -> bool {
+        self.missing().is_empty()
+    }
+
+    /// The `(entity, key_id)` of the signers we're still waiting for.
+    pub fn missing(&self) -> Vec<(&str, &str)> {
+        self.expected
+            .keys()
+            .filter(|&&(ref entity, ref key_id)| {
+                self.signatures.get_signature(entity, key_id).is_none()
+            })
+            .map(|&(ref entity, ref key_id)| (&entity[..], &key_id[..]))
+            .collect()
+    }
+
+    /// The signatures collected so far.
+    pub fn signatures(&self) -> &SignatureMap {
+        &self.signatures
+    }
+
+    /// Add the signatures collected so far to the object.
+    pub fn apply<T>(&self, obj: &mut T)
+        where T: SignedMut
+    {
+        obj.signatures_mut().merge(&self.signatures);
+    }
+
+    /// Return the collected signatures if complete, otherwise hand back the
+    /// collector.
+    pub fn finish(self) -> Result<SignatureMap, SignatureCollector> {
+        if self.is_complete() {
+            Ok(self.signatures)
+        } else {
+            Err(self)
+        }
+    }
+}
+
+impl AsCanonical for SignatureCollector {
+    fn as_canonical(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&self.canonical)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frozen::FrozenStruct;
+    use keys::{NamedPublicKey, SecretKey, SigningKeyPair, VerifyKey, VerifyResult};
+    use rustc_serialize::base64::FromBase64;
+    use serde_json::Value;
+    use signed::{Signed, SimpleSigned, Signatures};
+
+    type SimpleFrozen<'a> = FrozenStruct<'a, SimpleSigned, Value>;
+
+    #[test]
+    fn collect() {
+        let seed = "YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA1".from_base64().unwrap();
+        let key_a = SigningKeyPair::from_seed(&seed, "a.example", "ed25519:1").unwrap();
+        let key_b = SigningKeyPair::generate("b.example", "ed25519:1");
+        let key_c = SigningKeyPair::generate("c.example", "ed25519:1");
+
+        let mut frozen: SimpleFrozen = FrozenStruct::from_slice(br#"{"a":1}"#).unwrap();
+
+        let mut collector = SignatureCollector::new(&frozen,
+                                                    vec![VerifyKey::from_signing_key(&key_a),
+                                                         VerifyKey::from_signing_key(&key_b)]);
+        assert_eq!(collector.missing(),
+                   vec![("a.example", "ed25519:1"), ("b.example", "ed25519:1")]);
+
+        let sig_a = key_a.sign_detached(&collector);
+        collector.add_signature("a.example", "ed25519:1", sig_a).unwrap();
+        assert!(!collector.is_complete());
+        assert_eq!(collector.missing(), vec![("b.example", "ed25519:1")]);
+
+        let sig_c = key_c.sign_detached(&collector);
+        assert_eq!(collector.add_signature("c.example", "ed25519:1", sig_c),
+                   Err(CollectError::UnexpectedSigner));
+        assert_eq!(collector.add_signature("b.example", "ed25519:1", sig_c),
+                   Err(CollectError::InvalidSignature));
+
+        let sig_b = key_b.sign_detached(&collector);
+        collector.add_signature("b.example", "ed25519:1", sig_b).unwrap();
+        assert!(collector.is_complete());
+
+        collector.apply(&mut frozen);
+        assert_eq!(VerifyKey::from_signing_key(&key_a).verify(&frozen),
+                   VerifyResult::Valid);
+        assert_eq!(VerifyKey::from_signing_key(&key_b).verify(&frozen),
+                   VerifyResult::Valid);
+
+        let sigs = collector.finish().unwrap();
+        assert_eq!(sigs.get_entities().count(), 2);
+        assert_eq!(frozen.signatures().as_map(), sigs.as_map());
+    }
+}
@@ -0,0 +1,149 @@
+//! Comparing values the caller expects, such as entity names, key IDs and
+//! fingerprints, against ones taken from untrusted input.
+//!
+//! The comparisons take the same time wherever the values differ, and only
+//! normalize in ways that can't make two different identifiers equal. Input
+//! that would need anything cleverer, e.g. non-ASCII server names, never
+//! matches.
+
+use rustc_serialize::base64::{FromBase64, ToBase64};
+
+use UNPADDED_BASE64;
+use algorithm::KeyId;
+
+
+/// Compare two byte strings in time that depends only on their lengths.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+/// Compare entity names, e.g. `example.com:8448`.
+///
+/// The host is case insensitive and may have a trailing dot. Internationalized
+/// names have to be in their punycode form; anything outside printable ASCII
+/// doesn't match.
+pub fn entity_eq(expected: &str, supplied: &str) -> bool {
+    match (normalize_entity(expected), normalize_entity(supplied)) {
+        (Some(expected), Some(supplied)) => constant_time_eq(&expected, &supplied),
+        _ => false,
+    }
+}
+
+fn normalize_entity(entity: &str) -> Option<Vec<u8>> {
+    if entity.is_empty() || entity.bytes().any(|b| b <= b' ' || b >= 0x7f) {
+        return None;
+    }
+
+    // Split off the port, taking care not to mistake part of an IPv6 literal
+    // for one.
+    let (host, port) = match entity.rfind(':') {
+        Some(idx) if !entity.ends_with(']') && entity[idx + 1..].bytes().all(is_digit) => {
+            (&entity[..idx], &entity[idx..])
+        }
+        _ => (entity, ""),
+    };
+    let host = if host.len() > 1 && host.ends_with('.') {
+        &host[..host.len() - 1]
+    } else {
+        host
+    };
+
+    let mut normalized: Vec<u8> = host.bytes()
+                                      .map(|b| if b'A' <= b && b <= b'Z' { b + 32 } else { b })
+                                      .collect();
+    normalized.extend_from_slice(port.as_bytes());
+    Some(normalized)
+}
+
+fn is_digit(b: u8) -> bool {
+    b'0' <= b && b <= b'9'
+}
+
+
+/// Compare key IDs, e.g. `ed25519:auto`. These are case sensitive and aren't
+/// normalized; a supplied ID that isn't of the form `algorithm:version`
+/// doesn't match.
+pub fn key_id_eq(expected: &str, supplied: &str) -> bool {
+    KeyId::parse(supplied).is_some() && constant_time_eq(expected.as_bytes(), supplied.as_bytes())
+}
+
+
+/// Compare raw fingerprint bytes, e.g. a certificate hash, against a base64
+/// encoded one.
+///
+/// The encoding may or may not be padded, but must otherwise be the exact
+/// encoding of the bytes: whitespace, the URL safe alphabet and non-zero
+/// trailing bits are all rejected.
+pub fn fingerprint_eq(expected: &[u8], supplied_b64: &str) -> bool {
+    let unpadded = supplied_b64.trim_end_matches('=');
+    let padding = supplied_b64.len() - unpadded.len();
+    if padding > 0 && (supplied_b64.len() % 4 != 0 || padding > 2) {
+        return false;
+    }
+
+    let decoded = match unpadded.from_base64() {
+        Ok(decoded) => decoded,
+        Err(_) => return false,
+    };
+    if decoded.to_base64(UNPADDED_BASE64) != unpadded {
+        return false;
+    }
+    constant_time_eq(expected, &decoded)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_time() {
+        assert!(constant_time_eq(b"", b""));
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+
+    #[test]
+    fn entities() {
+        assert!(entity_eq("example.com", "example.com"));
+        assert!(entity_eq("example.com", "Example.COM"));
+        assert!(entity_eq("example.com", "example.com."));
+        assert!(entity_eq("example.com:8448", "EXAMPLE.com.:8448"));
+        assert!(entity_eq("[::1]:8448", "[::1]:8448"));
+        assert!(entity_eq("[::A]", "[::a]"));
+
+        assert!(!entity_eq("example.com", "example.com:8448"));
+        assert!(!entity_eq("example.com", "example.com.."));
+        assert!(!entity_eq("example.com", " example.com"));
+        assert!(!entity_eq("example.com", "example.com\0"));
+        assert!(!entity_eq("xn--bcher-kva.example", "bücher.example"));
+        assert!(!entity_eq("", ""));
+    }
+
+    #[test]
+    fn key_ids() {
+        assert!(key_id_eq("ed25519:auto", "ed25519:auto"));
+        assert!(!key_id_eq("ed25519:auto", "ED25519:auto"));
+        assert!(!key_id_eq("ed25519:auto", "ed25519:auto "));
+        assert!(!key_id_eq("auto", "auto"));
+    }
+
+    #[test]
+    fn fingerprints() {
+        let fingerprint = b"\xfb\xff";
+        assert!(fingerprint_eq(fingerprint, "+/8"));
+        assert!(fingerprint_eq(fingerprint, "+/8="));
+
+        assert!(!fingerprint_eq(fingerprint, "-_8"));
+        assert!(!fingerprint_eq(fingerprint, "+/9"));
+        assert!(!fingerprint_eq(fingerprint, "+/8=="));
+        assert!(!fingerprint_eq(fingerprint, "+/\n8"));
+        assert!(!fingerprint_eq(fingerprint, "+/8A"));
+        assert!(!fingerprint_eq(b"", "=="));
+    }
+}
@@ -0,0 +1,243 @@
+//! Responding to a compromised signing key: revoking it, republishing the
+//! entity's keys without it, announcing the incident and no longer trusting
+//! what it signed.
+
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+
+use serde_json::{self, Value};
+
+use error::Error;
+use keys::{KeyRing, NamedSecretKey, VerifyKey};
+use keystore::MemoryKeyStore;
+use signed::Signed;
+use value::SignedValue;
+
+
+#[derive(Debug)]
+pub enum CompromiseError {
+    Json(serde_json::Error),
+    /// The key ring has no key with the given key ID.
+    UnknownKey(String),
+    /// Removing the key would leave nothing to sign with.
+    NoSigningKeys,
+    /// A key or signature couldn't be decoded.
+    Decode(Error),
+}
+
+impl From<serde_json::Error> for CompromiseError {
+    fn from(err: serde_json::Error) -> CompromiseError {
+        CompromiseError::Json(err)
+    }
+}
+
+impl From<Error> for CompromiseError {
+    fn from(err: Error) -> CompromiseError {
+        match err {
+            Error::Json(err) => CompromiseError::Json(err),
+            err => CompromiseError::Decode(err),
+        }
+    }
+}
+
+impl fmt::Display for CompromiseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CompromiseError::Json(ref err) => write!(f, "Invalid JSON: {}", err),
+            CompromiseError::UnknownKey(ref key_id) => write!(f, "Unknown key {}", key_id),
+            CompromiseError::NoSigningKeys => write!(f, "No signing keys would be left"),
+            CompromiseError::Decode(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl error::Error for CompromiseError {
+    fn description(&self) -> &str {
+        match *self {
+            CompromiseError::Json(_) => "invalid JSON",
+            CompromiseError::UnknownKey(_) => "unknown key",
+            CompromiseError::NoSigningKeys => "no signing keys would be left",
+            CompromiseError::Decode(ref err) => error::Error::description(err),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            CompromiseError::Json(ref err) => Some(err),
+            CompromiseError::Decode(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+
+/// The artifacts to publish after a key compromise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompromiseResponse {
+    /// The compromised key, as returned by `revocation_entry`.
+    pub revocation: Value,
+    /// A server key response that no longer lists the key.
+    pub key_response: Vec<u8>,
+    /// A statement of the incident signed by the remaining keys.
+    pub statement: Vec<u8>,
+}
+
+
+/// Describe a revoked key, with when and why it was revoked. Timestamps are in
+/// milliseconds since the epoch.
+pub fn revocation_entry(key: &VerifyKey, revoked_ts: u64, reason: &str) -> Value {
+    let mut entry = BTreeMap::new();
+    entry.insert("entity".to_string(), Value::String(key.entity.clone()));
+    entry.insert("key_id".to_string(), Value::String(key.key_id.clone()));
+    entry.insert("key".to_string(), Value::String(key.public_key_b64()));
+    entry.insert("revoked_ts".to_string(), Value::U64(revoked_ts));
+    entry.insert("reason".to_string(), Value::String(reason.to_string()));
+    Value::Object(entry)
+}
+
+/// Build an incident statement listing the revocations, signed by every
+/// current signing key in the ring.
+pub fn incident_statement(ring: &KeyRing,
+                          revocations: &[Value],
+                          issued_ts: u64)
+                          -> serde_json::Result<Vec<u8>> {
+    let mut statement = BTreeMap::new();
+    statement.insert("type".to_string(),
+                     Value::String("key_compromise".to_string()));
+    statement.insert("entity".to_string(), Value::String(ring.entity().to_string()));
+    statement.insert("issued_ts".to_string(), Value::U64(issued_ts));
+    statement.insert("revoked_keys".to_string(), Value::Array(revocations.to_vec()));
+
+    let mut signed = try!(SignedValue::from_value(Value::Object(statement)));
+    for key in ring.signing_keys() {
+        key.sign(&mut signed);
+    }
+    signed.to_vec()
+}
+
+/// Remove the compromised key from the ring and produce the artifacts to
+/// publish. The ring is left untouched on error.
+pub fn respond_to_compromise(ring: &mut KeyRing,
+                             key_id: &str,
+                             now: u64,
+                             valid_until_ts: u64,
+                             reason: &str)
+                             -> Result<CompromiseResponse, CompromiseError> {
+    let remaining = ring.signing_keys().iter().filter(|k| k.key_id != key_id).count();
+    if remaining == 0 {
+        return Err(CompromiseError::NoSigningKeys);
+    }
+
+    // Everything is built from a copy, so that the key is only removed from
+    // the ring once nothing else can fail.
+    let mut updated = ring.clone();
+    let key = try!(updated.remove_key(key_id)
+                          .ok_or_else(|| CompromiseError::UnknownKey(key_id.to_string())));
+    let revocation = revocation_entry(&key, now, reason);
+
+    let response = CompromiseResponse {
+        key_response: try!(updated.server_key_response(valid_until_ts)),
+        statement: try!(incident_statement(&updated, &[revocation.clone()], now)),
+        revocation: revocation,
+    };
+    *ring = updated;
+    Ok(response)
+}
+
+
+/// Whether the entity's only signature on the object is by the given key,
+/// i.e. whether the object can no longer be attributed to the entity once the
+/// key is distrusted.
+pub fn signed_only_by<T>(obj: &T, entity: &str, key_id: &str) -> bool
+    where T: Signed
+{
+    obj.signatures()
+       .get(entity)
+       .map_or(false, |sigs| sigs.len() == 1 && sigs.contains_key(key_id))
+}
+
+/// Distrust the key in the store and drop the previously accepted objects
+/// that only it signed for its entity.
+pub fn distrust<T>(store: &mut MemoryKeyStore, entity: &str, key_id: &str, accepted: &mut Vec<T>)
+    where T: Signed
+{
+    store.distrust_key(entity, key_id);
+    accepted.retain(|obj| !signed_only_by(obj, entity, key_id));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{KeyRing, NamedPublicKey, NamedSecretKey, SigningKeyPair, VerifyKey,
+               VerifyResult, validate_fetched_key_response};
+    use keystore::{KeyStore, MemoryKeyStore};
+    use value::SignedValue;
+
+    #[test]
+    fn respond() {
+        let compromised = SigningKeyPair::generate("example.com", "ed25519:a");
+        let good = SigningKeyPair::generate("example.com", "ed25519:b");
+
+        let mut ring = KeyRing::new("example.com");
+        ring.add_signing_key(compromised.clone()).unwrap();
+
+        match respond_to_compromise(&mut ring, "ed25519:a", 1000, 5000, "leaked") {
+            Err(CompromiseError::NoSigningKeys) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(ring.signing_keys().len(), 1);
+
+        ring.add_signing_key(good.clone()).unwrap();
+        match respond_to_compromise(&mut ring, "ed25519:c", 1000, 5000, "leaked") {
+            Err(CompromiseError::UnknownKey(ref key_id)) if key_id == "ed25519:c" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!(ring.signing_keys().len(), 2);
+
+        let response = respond_to_compromise(&mut ring, "ed25519:a", 1000, 5000, "leaked")
+                           .unwrap();
+        assert_eq!(ring.signing_keys(), &[good.clone()][..]);
+
+        assert_eq!(response.revocation.find("key").and_then(|k| k.as_str()),
+                   Some(&compromised.public_key_b64()[..]));
+
+        let keys = validate_fetched_key_response(&response.key_response, "example.com", 2000, 4000)
+                       .unwrap();
+        assert_eq!(keys.verify_keys.len(), 1);
+        assert_eq!(keys.verify_keys[0].key_id, "ed25519:b");
+        assert!(keys.old_verify_keys.is_empty());
+
+        let statement = SignedValue::from_slice(&response.statement).unwrap();
+        assert_eq!(VerifyKey::from_signing_key(&good).verify(&statement),
+                   VerifyResult::Valid);
+        assert_eq!(VerifyKey::from_signing_key(&compromised).verify(&statement),
+                   VerifyResult::Unsigned);
+    }
+
+    #[test]
+    fn distrust_accepted() {
+        let compromised = SigningKeyPair::generate("example.com", "ed25519:a");
+        let good = SigningKeyPair::generate("example.com", "ed25519:b");
+
+        let mut only_compromised = SignedValue::from_slice(br#"{"a":1}"#).unwrap();
+        compromised.sign(&mut only_compromised);
+        let mut both = SignedValue::from_slice(br#"{"a":2}"#).unwrap();
+        compromised.sign(&mut both);
+        good.sign(&mut both);
+
+        let mut store = MemoryKeyStore::new();
+        store.add_key(VerifyKey::from_signing_key(&compromised));
+        store.add_key(VerifyKey::from_signing_key(&good));
+
+        let mut accepted = vec![only_compromised, both.clone()];
+        distrust(&mut store, "example.com", "ed25519:a", &mut accepted);
+
+        assert_eq!(accepted, vec![both]);
+        assert!(store.get_key("example.com", "ed25519:a").is_none());
+        assert!(store.is_distrusted("example.com", "ed25519:a"));
+        assert!(!store.try_add_key(VerifyKey::from_signing_key(&compromised)));
+        assert!(store.get_key("example.com", "ed25519:b").is_some());
+    }
+}
@@ -0,0 +1,124 @@
+//! The error type for decoding keys, signatures and signed objects.
+
+use std::error;
+use std::fmt;
+use std::result;
+
+use serde_json;
+
+use ser::CanonicalError;
+
+
+#[derive(Debug)]
+pub enum Error {
+    Json(serde_json::Error),
+    /// A value that should be base64 encoded isn't.
+    Base64Decode,
+    /// A value that should be hex encoded isn't.
+    HexDecode,
+    /// A value that should be a multibase encoded ed25519 key isn't.
+    InvalidMultibase,
+    /// A decoded public key had the wrong number of bytes.
+    InvalidKeyLength { expected: usize, found: usize },
+    /// An ed25519 public key is a point of small order, which would let
+    /// forged signatures verify.
+    SmallOrderKey,
+    /// An ed25519 public key isn't in its canonical encoding.
+    NonCanonicalKey,
+    /// A decoded seed had the wrong number of bytes.
+    InvalidSeedLength { expected: usize, found: usize },
+    /// A decoded signature had the wrong number of bytes.
+    InvalidSignatureLength { expected: usize, found: usize },
+    /// The `signatures` block isn't a map of entity to key ID to signature.
+    MalformedSignaturesBlock(String),
+    /// A serialized or canonical copy would have been larger than
+    /// `max_bytes`.
+    OutputTooLarge { max_bytes: usize },
+    /// The input was longer than `max_bytes`, so wasn't parsed.
+    InputTooLarge { max_bytes: usize },
+    /// The input couldn't be canonicalized with the options given.
+    Canonical(CanonicalError),
+}
+
+pub type Result<T> = result::Result<T, Error>;
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Error {
+        Error::Json(err)
+    }
+}
+
+impl From<CanonicalError> for Error {
+    fn from(err: CanonicalError) -> Error {
+        match err {
+            CanonicalError::Json(err) => Error::Json(err),
+            CanonicalError::OutputTooLarge { max_bytes } => {
+                Error::OutputTooLarge { max_bytes: max_bytes }
+            }
+            err => Error::Canonical(err),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Json(ref err) => write!(f, "Invalid JSON: {}", err),
+            Error::Base64Decode => write!(f, "Invalid base64"),
+            Error::HexDecode => write!(f, "Invalid hex"),
+            Error::InvalidMultibase => write!(f, "Invalid multibase ed25519 key"),
+            Error::InvalidKeyLength { expected, found } => {
+                write!(f, "Invalid key length: expected {} bytes, found {}", expected, found)
+            }
+            Error::SmallOrderKey => write!(f, "Public key is a point of small order"),
+            Error::NonCanonicalKey => write!(f, "Public key isn't canonically encoded"),
+            Error::InvalidSeedLength { expected, found } => {
+                write!(f, "Invalid seed length: expected {} bytes, found {}", expected, found)
+            }
+            Error::InvalidSignatureLength { expected, found } => {
+                write!(f,
+                       "Invalid signature length: expected {} bytes, found {}",
+                       expected,
+                       found)
+            }
+            Error::MalformedSignaturesBlock(ref msg) => {
+                write!(f, "Malformed signatures block: {}", msg)
+            }
+            Error::OutputTooLarge { max_bytes } => {
+                write!(f, "Output larger than {} bytes", max_bytes)
+            }
+            Error::InputTooLarge { max_bytes } => {
+                write!(f, "Input larger than {} bytes", max_bytes)
+            }
+            Error::Canonical(ref err) => write!(f, "Canonical JSON error: {}", err),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::Json(_) => "invalid JSON",
+            Error::Base64Decode => "invalid base64",
+            Error::HexDecode => "invalid hex",
+            Error::InvalidMultibase => "invalid multibase",
+            Error::InvalidKeyLength { .. } => "invalid key length",
+            Error::SmallOrderKey => "small order public key",
+            Error::NonCanonicalKey => "non-canonical public key",
+            Error::InvalidSeedLength { .. } => "invalid seed length",
+            Error::InvalidSignatureLength { .. } => "invalid signature length",
+            Error::MalformedSignaturesBlock(_) => "malformed signatures block",
+            Error::OutputTooLarge { .. } => "output too large",
+            Error::InputTooLarge { .. } => "input too large",
+            Error::Canonical(_) => "canonical JSON error",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            Error::Json(ref err) => Some(err),
+            Error::Canonical(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
@@ -3,14 +3,17 @@ use std::collections::BTreeMap;
 use std::fmt::Debug;
 use std::ops::Deref;
 
-use serde::{Deserialize, Serialize};
+use serde;
+use serde::{Deserialize, Serialize, Serializer};
 use serde_json;
 
-use ser::canonicalize;
-use ser::signatures::Base64Signature;
+use error::Error;
+use ser::{CanonicalOptions, UNSIGNED_KEYS, canonicalize_with, encode_canonically,
+          encode_canonically_limited, replace_non_finite};
+use ser::signatures::SignatureMap;
 
 
-use signed::{AsCanonical, GetUnsigned, Signed, SignedMut, Signatures, SignaturesMut};
+use signed::{AsCanonical, GetUnsigned, Signed, SignedMut};
 
 
 #[derive(Debug)]
@@ -39,52 +42,222 @@ impl<'a, T, U> FrozenStruct<'a, T, U>
     where T: Debug + Signed + SignedMut + Deserialize,
           U: Debug + Serialize + Deserialize
 {
-    pub fn from_slice(bytes: &'a [u8]) -> Result<FrozenStruct<'a, T, U>, serde_json::Error> {
+    pub fn from_slice(bytes: &'a [u8]) -> Result<FrozenStruct<'a, T, U>, Error> {
+        FrozenStruct::parse(bytes, None)
+    }
+
+    /// Like `from_slice`, but fail with `Error::OutputTooLarge` rather than
+    /// use more than `max_bytes` for the canonical copy.
+    ///
+    /// The input can be much larger than its canonical form, e.g. because of
+    /// whitespace or a large `unsigned` block, so it has a limit of its own:
+    /// input longer than `max_input_bytes` is refused with
+    /// `Error::InputTooLarge` before it's parsed.
+    pub fn from_slice_limited(bytes: &'a [u8],
+                              max_input_bytes: usize,
+                              max_bytes: usize)
+                              -> Result<FrozenStruct<'a, T, U>, Error> {
+        if bytes.len() > max_input_bytes {
+            return Err(Error::InputTooLarge { max_bytes: max_input_bytes });
+        }
+        FrozenStruct::parse(bytes, Some(max_bytes))
+    }
+
+    /// Like `from_slice`, but refuses input that `canonicalize_with` would
+    /// refuse with `options`.
+    ///
+    /// If `options.non_finite` quarantines non-finite numbers and there are
+    /// any, the original bytes aren't kept, as they don't match the parsed
+    /// object; it's re-serialized instead.
+    pub fn from_slice_with(bytes: &'a [u8],
+                           options: &CanonicalOptions)
+                           -> Result<FrozenStruct<'a, T, U>, Error> {
+        let canonical = try!(canonicalize_with(bytes, options));
+        let input = try!(replace_non_finite(bytes, options.non_finite));
+        let mut val: serde_json::Value = try!(serde_json::from_slice(&input));
+        let signatures = try!(take_signatures(&mut val));
+
+        let serialized = match input {
+            Cow::Borrowed(_) => Some(Cow::Borrowed(bytes)),
+            Cow::Owned(_) => None,
+        };
+        FrozenStruct::build(val, signatures, serialized, canonical)
+    }
+
+    fn parse(bytes: &'a [u8], max_bytes: Option<usize>) -> Result<FrozenStruct<'a, T, U>, Error> {
         let mut val: serde_json::Value = try!(serde_json::from_slice(bytes));
-        let unsigned = if let Some(obj) = val.as_object_mut() {
-            if let Some(val) = obj.remove("unsigned") {
-                Some(try!(serde_json::from_value(val)))
-            } else {
-                None
-            }
-        } else {
-            None
+        let signatures = try!(take_signatures(&mut val));
+        let canonical = match max_bytes {
+            Some(max_bytes) => try!(encode_canonically_limited(&val, UNSIGNED_KEYS, max_bytes)),
+            None => try!(encode_canonically(&val)),
         };
+        FrozenStruct::build(val, signatures, Some(Cow::Borrowed(bytes)), canonical)
+    }
+
+    fn build(mut val: serde_json::Value,
+             signatures: Option<SignatureMap>,
+             serialized: Option<Cow<'a, [u8]>>,
+             canonical: Vec<u8>)
+             -> Result<FrozenStruct<'a, T, U>, Error> {
+        let unsigned = try!(take_unsigned(&mut val));
         Ok(FrozenStruct {
-            parsed: try!(serde_json::from_value(val)),
-            serialized: Some(Cow::Borrowed(bytes)),
-            canonical: Cow::Owned(try!(canonicalize(bytes))),
+            parsed: try!(with_signatures(val, signatures)),
+            serialized: serialized,
+            canonical: Cow::Owned(canonical),
             unsigned: unsigned,
         })
     }
 
-    pub fn serialize(&'a self) -> serde_json::Result<Cow<'a, [u8]>> {
-        if let Some(ref ser) = self.serialized {
-            Ok(Cow::Borrowed(&ser))
-        } else {
-            let mut val: serde_json::Value = try!(serde_json::from_slice(&self.canonical));
-            if let Some(obj) = val.as_object_mut() {
-                let sigs = self.parsed.signatures().as_map();
-                let sigs2: BTreeMap<&str, BTreeMap<&str, Base64Signature>> =
-                    sigs.into_iter()
-                        .map(|(k, v)| {
-                            let m: BTreeMap<&str, Base64Signature> =
-                                v.into_iter()
-                                 .map(|(n, s)| (n, Base64Signature::from(*s)))
-                                 .collect();
-                            (k, m)
-                        })
-                        .collect();
-                obj.insert(String::from("signatures"), serde_json::to_value(&sigs2));
-
-                if let Some(ref unsigned) = self.unsigned {
-                    obj.insert(String::from("unsigned"), serde_json::to_value(unsigned));
-                }
+    /// Update from a new serialization of the same object, e.g. one that a
+    /// relay has annotated. If only `signatures` and `unsigned` have changed
+    /// they're replaced and the existing canonical form is kept, so the object
+    /// can be re-signed or forwarded without re-canonicalizing it.
+    ///
+    /// Returns false, leaving the struct untouched, if anything else changed.
+    pub fn update_from_slice(&mut self, bytes: &'a [u8]) -> Result<bool, Error> {
+        let mut val: serde_json::Value = try!(serde_json::from_slice(bytes));
+        let signatures = try!(take_signatures(&mut val));
+        let unsigned = try!(take_unsigned(&mut val));
+
+        let canonical: serde_json::Value = try!(serde_json::from_slice(&self.canonical));
+        if val != canonical {
+            return Ok(false);
+        }
+
+        self.parsed = try!(with_signatures(val, signatures));
+        self.serialized = Some(Cow::Borrowed(bytes));
+        self.unsigned = unsigned;
+        Ok(true)
+    }
+
+    pub fn unsigned(&self) -> Option<&U> {
+        self.unsigned.as_ref()
+    }
+
+    /// Replace the `unsigned` block. This isn't part of the canonical form, so
+    /// the object doesn't need re-canonicalizing before being re-signed.
+    pub fn set_unsigned(&mut self, unsigned: Option<U>) {
+        self.serialized = None;
+        self.unsigned = unsigned;
+    }
+
+    /// The full object: the canonical content with the current `signatures`
+    /// and `unsigned` re-attached. Borrowed if it hasn't changed since it was
+    /// parsed or last serialized with `serialized`.
+    pub fn serialize(&self) -> serde_json::Result<Cow<[u8]>> {
+        match self.serialized {
+            Some(ref ser) => Ok(Cow::Borrowed(&ser[..])),
+            None => Ok(Cow::Owned(try!(serde_json::to_vec(&try!(self.to_value()))))),
+        }
+    }
+
+    /// Like `serialize`, but fail with `Error::OutputTooLarge` rather than
+    /// use more than `max_bytes` for the output.
+    ///
+    /// A re-serialized object is encoded canonically, `signatures` and
+    /// `unsigned` included. As that leaves the content as it is, the object is
+    /// refused without parsing the content if the canonical copy alone is too
+    /// large.
+    pub fn serialize_limited(&self, max_bytes: usize) -> Result<Cow<[u8]>, Error> {
+        let len = match self.serialized {
+            Some(ref ser) => ser.len(),
+            None => self.canonical.len(),
+        };
+        if len > max_bytes {
+            return Err(Error::OutputTooLarge { max_bytes: max_bytes });
+        }
+
+        match self.serialized {
+            Some(ref ser) => Ok(Cow::Borrowed(&ser[..])),
+            None => {
+                let val = try!(self.to_value());
+                Ok(Cow::Owned(try!(encode_canonically_limited(&val, &[], max_bytes))))
+            }
+        }
+    }
+
+    /// Like `serialize`, but caches the result until the signatures or
+    /// `unsigned` are next changed.
+    pub fn serialized(&mut self) -> serde_json::Result<&[u8]> {
+        let ser = match self.serialized.take() {
+            Some(ser) => ser,
+            None => Cow::Owned(try!(serde_json::to_vec(&try!(self.to_value())))),
+        };
+        Ok(&**self.serialized.get_or_insert(ser))
+    }
+
+    fn to_value(&self) -> serde_json::Result<serde_json::Value> {
+        let mut val: serde_json::Value = try!(serde_json::from_slice(&self.canonical));
+        if let Some(obj) = val.as_object_mut() {
+            obj.insert(String::from("signatures"),
+                       serde_json::to_value(self.parsed.signatures()));
+
+            if let Some(ref unsigned) = self.unsigned {
+                obj.insert(String::from("unsigned"), serde_json::to_value(unsigned));
             }
-            let ser = try!(serde_json::to_vec(&val));
-            Ok(Cow::Owned(ser))
+        }
+        Ok(val)
+    }
+}
+
+impl<'a, T, U> Serialize for FrozenStruct<'a, T, U>
+    where T: Debug + Signed + SignedMut + Deserialize,
+          U: Debug + Serialize + Deserialize
+{
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: Serializer
+    {
+        let val = try!(self.to_value()
+                           .map_err(|e| <S::Error as serde::ser::Error>::custom(e.to_string())));
+        val.serialize(serializer)
+    }
+}
+
+/// Remove the `signatures` block from the value, if any, and deserialize it,
+/// so that a bad one is reported as such rather than as a failure to
+/// deserialize `T`.
+fn take_signatures(val: &mut serde_json::Value) -> Result<Option<SignatureMap>, Error> {
+    let sigs = match val.as_object_mut().and_then(|obj| obj.remove("signatures")) {
+        Some(sigs) => sigs,
+        None => return Ok(None),
+    };
+    serde_json::from_value(sigs)
+        .map(Some)
+        .map_err(|e| Error::MalformedSignaturesBlock(e.to_string()))
+}
+
+/// Deserialize `T` from a value whose `signatures` block was removed by
+/// `take_signatures`, without parsing the signatures again.
+fn with_signatures<T>(mut val: serde_json::Value,
+                      signatures: Option<SignatureMap>)
+                      -> Result<T, serde_json::Error>
+    where T: SignedMut + Deserialize
+{
+    let signatures = match signatures {
+        Some(signatures) => signatures,
+        None => return serde_json::from_value(val),
+    };
+
+    // `T` may require the field, so it's given an empty one to replace.
+    if let Some(obj) = val.as_object_mut() {
+        obj.insert(String::from("signatures"),
+                   serde_json::Value::Object(BTreeMap::new()));
+    }
+    let mut parsed: T = try!(serde_json::from_value(val));
+    *parsed.signatures_mut() = signatures;
+    Ok(parsed)
+}
+
+/// Remove the `unsigned` block from the value, if any, and deserialize it.
+fn take_unsigned<U>(val: &mut serde_json::Value) -> Result<Option<U>, serde_json::Error>
+    where U: Deserialize
+{
+    if let Some(obj) = val.as_object_mut() {
+        if let Some(unsigned) = obj.remove("unsigned") {
+            return serde_json::from_value(unsigned).map(Some);
         }
     }
+    Ok(None)
 }
 
 impl<'a, T, U> Deref for FrozenStruct<'a, T, U>
@@ -102,7 +275,7 @@ impl<'a, T, U> Signed for FrozenStruct<'a, T, U>
     where T: Signed + Debug + Signed + SignedMut,
           U: Debug + Serialize + Deserialize
 {
-    fn signatures(&self) -> &Signatures {
+    fn signatures(&self) -> &SignatureMap {
         self.parsed.signatures()
     }
 }
@@ -111,7 +284,7 @@ impl<'a, T, U> SignedMut for FrozenStruct<'a, T, U>
     where T: SignedMut + Debug + Signed + SignedMut,
           U: Debug + Serialize + Deserialize
 {
-    fn signatures_mut(&mut self) -> &mut SignaturesMut {
+    fn signatures_mut(&mut self) -> &mut SignatureMap {
         self.serialized = None;
         self.parsed.signatures_mut()
     }
@@ -144,9 +317,13 @@ impl<'a, T, U> Clone for FrozenStruct<'a, T, U>
 #[cfg(test)]
 mod tests {
     use super::*;
-    use signed::{Signed, SignedMut, Signatures, SimpleSigned, AsCanonical};
+    use signed::{Signed, SignedMut, SimpleSigned, AsCanonical};
     use sodiumoxide::crypto::sign;
-    use serde_json::Value;
+    use error::Error;
+    use ser::{CanonicalError, CanonicalOptions, NonFinitePolicy};
+    use serde_json::{self, Value};
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
 
     #[test]
     fn from_slice() {
@@ -177,4 +354,141 @@ mod tests {
         let new_bytes = br#"{"old_verify_keys":{},"server_name":"jki.re","signatures":{"jki.re":{"ed25519:auto":"X2t7jN0jaJsiZWp57da9GqmQ874QFbukCMSqc5VclaB+2n4i8LPcZDkD6+fzg4tkfpSsiIDogkY4HWv1cnGhAg","ed25519:test":"X2t7jN0jaJsiZWp57da9GqmQ874QFbukCMSqc5VclaB+2n4i8LPcZDkD6+fzg4tkfpSsiIDogkY4HWv1cnGhAg"}},"tls_fingerprints":[{"sha256":"Big0aXVWZ/m0oEcHddgP4hTriTEvb4Jx6592W1mB5i4"}],"valid_until_ts":1462110302047,"verify_keys":{"ed25519:auto":{"key":"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI"}}}"#;
         assert_eq!(&frozen.serialize().unwrap()[..], &new_bytes[..]);
     }
+
+    #[test]
+    fn update_from_slice() {
+        let bytes = br#"{"a":1,"signatures":{},"unsigned":{"age":1}}"#;
+        let mut frozen: FrozenStruct<SimpleSigned, Value> = FrozenStruct::from_slice(bytes)
+                                                                .unwrap();
+
+        let relayed = br#"{"a":1,"signatures":{"jki.re":{"ed25519:auto":"X2t7jN0jaJsiZWp57da9GqmQ874QFbukCMSqc5VclaB+2n4i8LPcZDkD6+fzg4tkfpSsiIDogkY4HWv1cnGhAg"}},"unsigned":{"age":2}}"#;
+        assert!(frozen.update_from_slice(relayed).unwrap());
+        assert_eq!(&frozen.as_canonical()[..], &br#"{"a":1}"#[..]);
+        assert!(frozen.signatures().get_signature("jki.re", "ed25519:auto").is_some());
+        assert_eq!(frozen.unsigned().and_then(|u| u.find("age")).and_then(|a| a.as_u64()),
+                   Some(2));
+        assert_eq!(&frozen.serialize().unwrap()[..], &relayed[..]);
+
+        let modified = br#"{"a":2,"signatures":{}}"#;
+        assert!(!frozen.update_from_slice(modified).unwrap());
+        assert_eq!(&frozen.serialize().unwrap()[..], &relayed[..]);
+    }
+
+    #[test]
+    fn malformed_signatures() {
+        let bytes = br#"{"a":1,"signatures":{"jki.re":{"ed25519:auto":"AAAA"}}}"#;
+        match FrozenStruct::<SimpleSigned, Value>::from_slice(bytes) {
+            Err(Error::MalformedSignaturesBlock(ref msg)) if msg.contains("jki.re ed25519:auto") => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn limits() {
+        let bytes = br#"{"b":"xxxxxxxxxx","a":1,"unsigned":{"age":1}}"#;
+
+        // Input that's too large itself is refused before it's parsed.
+        match FrozenStruct::<SimpleSigned, Value>::from_slice_limited(bytes,
+                                                                      bytes.len() - 1,
+                                                                      bytes.len()) {
+            Err(Error::InputTooLarge { max_bytes }) => assert_eq!(max_bytes, bytes.len() - 1),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // The canonical form is checked separately, here `{"a":100.0}`.
+        let grows = br#"{"a":1e2}"#;
+        match FrozenStruct::<SimpleSigned, Value>::from_slice_limited(grows, 100, 10) {
+            Err(Error::OutputTooLarge { max_bytes: 10 }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert!(FrozenStruct::<SimpleSigned, Value>::from_slice_limited(grows, 100, 11).is_ok());
+
+        // Input larger than the limit on the canonical form is fine if its
+        // canonical form fits, here `{"a":1,"b":"xxxxxxxxxx"}`.
+        let canonical_len = br#"{"a":1,"b":"xxxxxxxxxx"}"#.len();
+        let mut frozen = FrozenStruct::<SimpleSigned, Value>::from_slice_limited(bytes,
+                                                                               bytes.len(),
+                                                                               canonical_len)
+                             .unwrap();
+        assert_eq!(&frozen.as_canonical()[..], &br#"{"a":1,"b":"xxxxxxxxxx"}"#[..]);
+
+        // Both from the original bytes and from re-serializing.
+        let reserialized = br#"{"a":1,"b":"xxxxxxxxxx","signatures":{},"unsigned":{"age":1}}"#;
+        for &expected in &[&bytes[..], &reserialized[..]] {
+            assert_eq!(&frozen.serialize_limited(expected.len()).unwrap()[..], expected);
+            match frozen.serialize_limited(expected.len() - 1) {
+                Err(Error::OutputTooLarge { .. }) => {}
+                res => panic!("Unexpected result: {:?}", res),
+            }
+            frozen.set_unsigned(Some(serde_json::from_str(r#"{"age":1}"#).unwrap()));
+        }
+    }
+
+    #[test]
+    fn from_slice_with() {
+        let bytes = br#"{"b":NaN,"a":1,"unsigned":{"age":1}}"#;
+
+        match FrozenStruct::<SimpleSigned, Value>::from_slice_with(bytes,
+                                                                   &CanonicalOptions::default()) {
+            Err(Error::Canonical(CanonicalError::NonFiniteNumber { offset: 5, token: "NaN" })) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let quarantine = CanonicalOptions {
+            non_finite: NonFinitePolicy::Quarantine,
+            ..CanonicalOptions::default()
+        };
+        let mut frozen = FrozenStruct::<SimpleSigned, Value>::from_slice_with(bytes, &quarantine)
+                             .unwrap();
+        assert_eq!(&frozen.as_canonical()[..], &br#"{"a":1,"b":null}"#[..]);
+        assert_eq!(frozen.serialized().unwrap(),
+                   &br#"{"a":1,"b":null,"signatures":{},"unsigned":{"age":1}}"#[..]);
+
+        // Input without any is kept as it is.
+        let bytes = br#"{"b":2,"a":1}"#;
+        let mut frozen = FrozenStruct::<SimpleSigned, Value>::from_slice_with(bytes, &quarantine)
+                             .unwrap();
+        assert_eq!(frozen.serialized().unwrap(), &bytes[..]);
+
+        match FrozenStruct::<SimpleSigned, Value>::from_slice_with(br#"{"a":1.5}"#,
+                                                                   &CanonicalOptions::strict()) {
+            Err(Error::Canonical(CanonicalError::Float { ref path })) if path == "/a" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let frozen = FrozenStruct::<SimpleSigned, Value>::from_slice_with(br#"{"a":1.0}"#,
+                                                                          &CanonicalOptions::jcs())
+                         .unwrap();
+        assert_eq!(&frozen.as_canonical()[..], &br#"{"a":1}"#[..]);
+    }
+
+    #[test]
+    fn serialize() {
+        let bytes = br#"{"a":1,"unsigned":{"age":1}}"#;
+        let mut frozen: FrozenStruct<SimpleSigned, Value> = FrozenStruct::from_slice(bytes)
+                                                                .unwrap();
+        match frozen.serialize().unwrap() {
+            Cow::Borrowed(ser) => assert_eq!(ser, &bytes[..]),
+            Cow::Owned(_) => panic!("Unchanged object was re-serialized"),
+        }
+        assert_eq!(frozen.serialized().unwrap(), &bytes[..]);
+
+        let sig = sign::Signature::from_slice(&[0; 64]).unwrap();
+        frozen.signatures_mut().add_signature("domain", "ed25519:1", sig);
+        frozen.set_unsigned(None);
+
+        let expected = br#"{"a":1,"signatures":{"domain":{"ed25519:1":"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA"}}}"#;
+        assert_eq!(&frozen.serialize().unwrap()[..], &expected[..]);
+        assert_eq!(frozen.serialized().unwrap(), &expected[..]);
+        match frozen.serialize().unwrap() {
+            Cow::Borrowed(ser) => assert_eq!(ser, &expected[..]),
+            Cow::Owned(_) => panic!("Cached serialization wasn't used"),
+        }
+
+        let mut outer = BTreeMap::new();
+        outer.insert("event", &frozen);
+        let outer_bytes = serde_json::to_vec(&outer).unwrap();
+        assert_eq!(&outer_bytes[..9], &br#"{"event":"#[..]);
+        assert_eq!(&outer_bytes[9..outer_bytes.len() - 1], &expected[..]);
+    }
 }
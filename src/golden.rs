@@ -0,0 +1,237 @@
+//! Golden-file regression tests for canonical output.
+//!
+//! A case is an input JSON document, the exact canonical bytes it has to
+//! encode to, and optionally the signature those bytes get from the suite's
+//! fixed seed. Any change to the encoder that would alter signed bytes for a
+//! known document shows up as a failing case.
+//!
+//! Cases are loaded from a directory holding `<name>.json`, `<name>.canonical`
+//! and, optionally, `<name>.sig` files, or added directly. Loading from a
+//! directory needs the `golden` cargo feature, so that the crate doesn't touch
+//! the filesystem otherwise. The crate's own corpus is in `tests/golden`;
+//! downstream users can run theirs the same way.
+
+use std::fmt;
+#[cfg(any(test, feature = "golden"))]
+use std::fs::{self, File};
+#[cfg(any(test, feature = "golden"))]
+use std::io::{self, Read};
+#[cfg(any(test, feature = "golden"))]
+use std::path::Path;
+
+use rustc_serialize::base64::ToBase64;
+use sodiumoxide::crypto::sign;
+
+use UNPADDED_BASE64;
+use error::Error;
+use keys::SigningKeyPair;
+use ser::canonicalize;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenCase {
+    pub name: String,
+    pub input: Vec<u8>,
+    pub canonical: Vec<u8>,
+    /// The unpadded base64 signature of `canonical`, if it should be checked.
+    pub signature: Option<String>,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GoldenMismatch {
+    /// The input couldn't be canonicalized.
+    Invalid(String),
+    Canonical { found: Vec<u8> },
+    Signature { found: String },
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenFailure {
+    pub name: String,
+    pub mismatch: GoldenMismatch,
+}
+
+impl fmt::Display for GoldenFailure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mismatch {
+            GoldenMismatch::Invalid(ref err) => write!(f, "{}: invalid input: {}", self.name, err),
+            GoldenMismatch::Canonical { ref found } => {
+                write!(f,
+                       "{}: canonical output changed to {}",
+                       self.name,
+                       String::from_utf8_lossy(found))
+            }
+            GoldenMismatch::Signature { ref found } => {
+                write!(f, "{}: signature changed to {}", self.name, found)
+            }
+        }
+    }
+}
+
+
+pub struct GoldenSuite {
+    key: SigningKeyPair,
+    cases: Vec<GoldenCase>,
+}
+
+impl GoldenSuite {
+    /// Create an empty suite whose signatures are made with the given ed25519
+    /// seed.
+    pub fn new(seed: &[u8]) -> Result<GoldenSuite, Error> {
+        Ok(GoldenSuite {
+            key: try!(SigningKeyPair::from_seed(seed, "golden", "ed25519:golden")),
+            cases: Vec::new(),
+        })
+    }
+
+    pub fn add_case(&mut self, case: GoldenCase) {
+        self.cases.push(case);
+    }
+
+    /// Add every case in the directory, in order of name, returning how many
+    /// there were. Each `<name>.json` needs a matching `<name>.canonical`.
+    #[cfg(any(test, feature = "golden"))]
+    pub fn load_dir<P: AsRef<Path>>(&mut self, dir: P) -> io::Result<usize> {
+        let dir = dir.as_ref();
+
+        let mut names = Vec::new();
+        for entry in try!(fs::read_dir(dir)) {
+            let path = try!(entry).path();
+            if path.extension().map_or(false, |ext| ext == "json") {
+                if let Some(name) = path.file_stem().and_then(|name| name.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+
+        for name in &names {
+            let input = try!(read_file(&dir.join(format!("{}.json", name))));
+            let canonical = try!(read_file(&dir.join(format!("{}.canonical", name))));
+            let signature = match read_file(&dir.join(format!("{}.sig", name))) {
+                Ok(sig) => Some(String::from_utf8_lossy(&sig).trim().to_string()),
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err),
+            };
+            self.add_case(GoldenCase {
+                name: name.clone(),
+                input: input,
+                canonical: canonical,
+                signature: signature,
+            });
+        }
+        Ok(names.len())
+    }
+
+    pub fn cases(&self) -> &[GoldenCase] {
+        &self.cases
+    }
+
+    /// The signature the suite's key gives the bytes, e.g. for writing a new
+    /// `.sig` file.
+    pub fn signature_for(&self, canonical: &[u8]) -> String {
+        sign::sign_detached(canonical, &self.key.secret).0.to_base64(UNPADDED_BASE64)
+    }
+
+    /// Check every case, returning the ones that failed.
+    pub fn run(&self) -> Vec<GoldenFailure> {
+        let mut failures = Vec::new();
+        for case in &self.cases {
+            let mismatch = match canonicalize(&case.input) {
+                Err(err) => Some(GoldenMismatch::Invalid(err.to_string())),
+                Ok(ref found) if *found != case.canonical => {
+                    Some(GoldenMismatch::Canonical { found: found.clone() })
+                }
+                Ok(ref found) => {
+                    let sig = self.signature_for(found);
+                    match case.signature {
+                        Some(ref expected) if *expected != sig => {
+                            Some(GoldenMismatch::Signature { found: sig })
+                        }
+                        _ => None,
+                    }
+                }
+            };
+            if let Some(mismatch) = mismatch {
+                failures.push(GoldenFailure {
+                    name: case.name.clone(),
+                    mismatch: mismatch,
+                });
+            }
+        }
+        failures
+    }
+}
+
+#[cfg(any(test, feature = "golden"))]
+fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    try!(try!(File::open(path)).read_to_end(&mut buf));
+    Ok(buf)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustc_serialize::base64::FromBase64;
+
+    fn suite() -> GoldenSuite {
+        let seed = "YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA1".from_base64().unwrap();
+        GoldenSuite::new(&seed).unwrap()
+    }
+
+    #[test]
+    fn corpus() {
+        let mut suite = suite();
+        let count = suite.load_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden")).unwrap();
+        assert!(count >= 4);
+
+        let failures = suite.run();
+        assert!(failures.is_empty(),
+                "{}",
+                failures.iter().map(|f| f.to_string()).collect::<Vec<_>>().join("\n"));
+    }
+
+    #[test]
+    fn mismatches() {
+        let mut suite = suite();
+        suite.add_case(GoldenCase {
+            name: "ok".to_string(),
+            input: br#"{"b":1,"a":2}"#.to_vec(),
+            canonical: br#"{"a":2,"b":1}"#.to_vec(),
+            signature: None,
+        });
+        suite.add_case(GoldenCase {
+            name: "canonical".to_string(),
+            input: br#"{"b":1,"a":2}"#.to_vec(),
+            canonical: br#"{"b":1,"a":2}"#.to_vec(),
+            signature: None,
+        });
+        suite.add_case(GoldenCase {
+            name: "signature".to_string(),
+            input: br#"{"a":2}"#.to_vec(),
+            canonical: br#"{"a":2}"#.to_vec(),
+            signature: Some("AAAA".to_string()),
+        });
+        suite.add_case(GoldenCase {
+            name: "invalid".to_string(),
+            input: b"{".to_vec(),
+            canonical: Vec::new(),
+            signature: None,
+        });
+
+        let failures = suite.run();
+        assert_eq!(failures.len(), 3);
+        assert_eq!(failures[0],
+                   GoldenFailure {
+                       name: "canonical".to_string(),
+                       mismatch: GoldenMismatch::Canonical { found: br#"{"a":2,"b":1}"#.to_vec() },
+                   });
+        assert_eq!(failures[1].mismatch,
+                   GoldenMismatch::Signature { found: suite.signature_for(br#"{"a":2}"#) });
+        assert_eq!(failures[2].name, "invalid");
+    }
+}
@@ -0,0 +1,180 @@
+//! Content hashes, i.e. the `hashes` object carried by e.g. Matrix events.
+//!
+//! The content hash is the SHA-256 of the canonical form of the object with
+//! `hashes`, `signatures` and `unsigned` removed. Unlike those, the `hashes`
+//! object is covered by signatures.
+
+use std::collections::BTreeMap;
+use std::ops::Deref;
+
+use serde_json;
+use sodiumoxide::crypto::hash::sha256;
+
+use ser::{UNHASHED_KEYS, encode_canonically_stripping};
+use ser::hashes::Base64Hash;
+use signed::AsCanonical;
+
+
+/// The key of SHA-256 hashes in the `hashes` object.
+pub const SHA256: &'static str = "sha256";
+
+
+pub trait Hashes {
+    fn get_hash(&self, algorithm: &str) -> Option<&sha256::Digest>;
+    fn get_hashes<'a>(&'a self) -> Box<Iterator<Item = (&'a str, &'a sha256::Digest)> + 'a>;
+}
+
+pub trait HashesMut {
+    fn add_hash(&mut self, algorithm: &str, hash: sha256::Digest);
+    fn clear(&mut self);
+}
+
+impl<H> Hashes for BTreeMap<String, H>
+    where H: Deref<Target = sha256::Digest>
+{
+    fn get_hash(&self, algorithm: &str) -> Option<&sha256::Digest> {
+        self.get(algorithm).map(|h| h.deref())
+    }
+
+    fn get_hashes<'a>(&'a self) -> Box<Iterator<Item = (&'a str, &'a sha256::Digest)> + 'a> {
+        Box::new(self.iter().map(|(a, h)| (&a[..], h.deref())))
+    }
+}
+
+impl<H> HashesMut for BTreeMap<String, H>
+    where H: From<sha256::Digest>
+{
+    fn add_hash(&mut self, algorithm: &str, hash: sha256::Digest) {
+        self.insert(algorithm.to_string(), H::from(hash));
+    }
+
+    fn clear(&mut self) {
+        self.clear();
+    }
+}
+
+
+pub trait Hashed {
+    fn hashes(&self) -> &BTreeMap<String, Base64Hash>;
+}
+
+pub trait HashedMut: Hashed {
+    fn hashes_mut(&mut self) -> &mut BTreeMap<String, Base64Hash>;
+}
+
+
+#[must_use]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashResult {
+    Valid,
+    Invalid,
+    Missing,
+}
+
+
+/// Compute the content hash of the object.
+pub fn compute_content_hash<T>(obj: &T) -> serde_json::Result<sha256::Digest>
+    where T: AsCanonical
+{
+    let val: serde_json::Value = try!(serde_json::from_slice(&obj.as_canonical()));
+    let content = try!(encode_canonically_stripping(&val, UNHASHED_KEYS));
+    Ok(sha256::hash(&content))
+}
+
+/// Check the object's SHA-256 content hash.
+pub fn verify_content_hash<T>(obj: &T) -> serde_json::Result<HashResult>
+    where T: AsCanonical + Hashed
+{
+    let expected = match obj.hashes().get_hash(SHA256) {
+        Some(hash) => hash,
+        None => return Ok(HashResult::Missing),
+    };
+
+    if *expected == try!(compute_content_hash(obj)) {
+        Ok(HashResult::Valid)
+    } else {
+        Ok(HashResult::Invalid)
+    }
+}
+
+/// Compute the object's content hash and add it to its `hashes`.
+pub fn add_content_hash<T>(obj: &mut T) -> serde_json::Result<()>
+    where T: AsCanonical + HashedMut
+{
+    let hash = try!(compute_content_hash(obj));
+    obj.hashes_mut().add_hash(SHA256, hash);
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+    use std::collections::BTreeMap;
+    use ser::encode_canonically;
+    use ser::hashes::Base64Hash;
+    use serde_json;
+    use signed::AsCanonical;
+    use sodiumoxide::crypto::hash::sha256;
+
+    struct Event {
+        content: serde_json::Value,
+        hashes: BTreeMap<String, Base64Hash>,
+    }
+
+    impl AsCanonical for Event {
+        fn as_canonical(&self) -> Cow<[u8]> {
+            let mut val = self.content.clone();
+            val.as_object_mut()
+               .unwrap()
+               .insert("hashes".to_string(), serde_json::to_value(&self.hashes));
+            Cow::Owned(encode_canonically(&val).unwrap())
+        }
+    }
+
+    impl Hashed for Event {
+        fn hashes(&self) -> &BTreeMap<String, Base64Hash> {
+            &self.hashes
+        }
+    }
+
+    impl HashedMut for Event {
+        fn hashes_mut(&mut self) -> &mut BTreeMap<String, Base64Hash> {
+            &mut self.hashes
+        }
+    }
+
+    #[test]
+    fn content_hash() {
+        let mut event = Event {
+            content: serde_json::from_str(r#"{"b":"x","a":1,"unsigned":{"age":3}}"#).unwrap(),
+            hashes: BTreeMap::new(),
+        };
+
+        assert_eq!(verify_content_hash(&event).unwrap(), HashResult::Missing);
+
+        add_content_hash(&mut event).unwrap();
+        assert_eq!(serde_json::to_string(&event.hashes).unwrap(),
+                   r#"{"sha256":"7PnpjsBkHiMRP/POi9x40N3SSYhlF/1Kf2jMg9TmVmc"}"#);
+        assert_eq!(verify_content_hash(&event).unwrap(), HashResult::Valid);
+
+        event.content.as_object_mut().unwrap().insert("a".to_string(), serde_json::Value::U64(2));
+        assert_eq!(verify_content_hash(&event).unwrap(), HashResult::Invalid);
+    }
+
+    #[test]
+    fn hashes_map() {
+        let hash = sha256::hash(b"{}");
+
+        let mut map: BTreeMap<String, Base64Hash> = BTreeMap::new();
+        assert!(map.get_hash(SHA256).is_none());
+
+        map.add_hash(SHA256, hash);
+        assert_eq!(map.get_hash(SHA256), Some(&hash));
+        assert_eq!(map.get_hashes().collect::<Vec<_>>(), vec![(SHA256, &hash)]);
+
+        HashesMut::clear(&mut map);
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,781 @@
+mod point;
+#[cfg(feature = "http")]
+mod ring;
+#[cfg(feature = "http")]
+mod server;
+mod verified;
+
+pub use self::point::check_public_key;
+#[cfg(feature = "http")]
+pub use self::ring::{KeyRing, KeyRingError, OldKeysIn, SigningKeysIn};
+#[cfg(feature = "http")]
+pub use self::server::{FetchedServerKeys, KeyResponseError, ServerKeys,
+                       validate_fetched_key_response};
+pub use self::verified::{VerifyError, verified_from_slice, verified_from_slice_with};
+
+use std::borrow::Cow;
+use std::error;
+use std::fmt;
+
+use sodiumoxide::crypto::hash::sha256;
+use sodiumoxide::crypto::sign;
+
+use rustc_serialize::base64::{FromBase64, ToBase64};
+use rustc_serialize::hex::{FromHex, ToHex};
+
+use UNPADDED_BASE64;
+use algorithm;
+use error::Error;
+use ser::signatures::SignatureMap;
+use signed::{AsCanonical, Signed, SignedMut};
+
+
+pub trait PublicKey {
+    fn public_key(&self) -> &sign::PublicKey;
+
+    fn verify_detached<T>(&self, sig: &sign::Signature, obj: &T) -> VerifyResultDetached
+        where T: AsCanonical
+    {
+        if sign::verify_detached(sig, &obj.as_canonical(), self.public_key()) {
+            VerifyResultDetached::Valid
+        } else {
+            VerifyResultDetached::Invalid
+        }
+    }
+}
+
+pub trait SecretKey {
+    fn secret_key(&self) -> &sign::SecretKey;
+
+    fn sign_detached<T>(&self, obj: &T) -> sign::Signature
+        where T: AsCanonical
+    {
+        sign::sign_detached(&obj.as_canonical(), &self.secret_key())
+    }
+}
+
+pub trait NamedKey {
+    fn entity(&self) -> &str;
+    fn key_id(&self) -> &str;
+}
+
+pub trait NamedPublicKey: PublicKey + NamedKey {
+    fn verify<T>(&self, obj: &T) -> VerifyResult
+        where T: AsCanonical + Signed
+    {
+        if !algorithm::is_supported_key_id(self.key_id()) {
+            return VerifyResult::UnsupportedAlgorithm;
+        }
+        if let Some(sig) = obj.signatures().get_signature(self.entity(), self.key_id()) {
+            if sign::verify_detached(sig, &obj.as_canonical(), self.public_key()) {
+                VerifyResult::Valid
+            } else {
+                VerifyResult::Invalid
+            }
+        } else {
+            VerifyResult::Unsigned
+        }
+    }
+}
+
+impl<T> NamedPublicKey for T where T: PublicKey + NamedKey {}
+
+/// Verify many objects, each against any number of keys, giving the results
+/// for each object's keys in the same order.
+///
+/// Each object is only encoded canonically once, however many keys it's
+/// checked against.
+pub fn verify_batch<T>(items: &[(&T, &[&VerifyKey])]) -> Vec<Vec<VerifyResult>>
+    where T: AsCanonical + Signed
+{
+    items.iter()
+         .map(|&(obj, keys)| {
+             let encoded = Encoded {
+                 canonical: obj.as_canonical(),
+                 obj: obj,
+             };
+             keys.iter().map(|key| key.verify(&encoded)).collect()
+         })
+         .collect()
+}
+
+/// An object along with its canonical encoding, for `verify_batch`.
+struct Encoded<'a, T: 'a> {
+    obj: &'a T,
+    canonical: Cow<'a, [u8]>,
+}
+
+impl<'a, T> AsCanonical for Encoded<'a, T> {
+    fn as_canonical(&self) -> Cow<[u8]> {
+        Cow::Borrowed(&self.canonical)
+    }
+}
+
+impl<'a, T> Signed for Encoded<'a, T>
+    where T: Signed
+{
+    fn signatures(&self) -> &SignatureMap {
+        self.obj.signatures()
+    }
+}
+
+pub trait NamedSecretKey: SecretKey + NamedKey {
+    fn sign<T>(&self, obj: &mut T)
+        where T: AsCanonical + SignedMut
+    {
+        let sig = sign::sign_detached(&obj.as_canonical(), self.secret_key());
+        obj.signatures_mut().add_signature(self.entity(), self.key_id(), sig);
+    }
+
+    /// Sign the object, returning the SHA-256 of the canonical bytes that were
+    /// signed so that it can be logged in place of the content.
+    fn sign_hashed<T>(&self, obj: &mut T) -> sha256::Digest
+        where T: AsCanonical + SignedMut
+    {
+        let (sig, hash) = {
+            let canonical = obj.as_canonical();
+            (sign::sign_detached(&canonical, self.secret_key()), sha256::hash(&canonical))
+        };
+        obj.signatures_mut().add_signature(self.entity(), self.key_id(), sig);
+        hash
+    }
+}
+
+impl<T> NamedSecretKey for T where T: SecretKey + NamedKey {}
+
+
+/// The SHA-256 of the canonical form of the object, as returned by
+/// `NamedSecretKey::sign_hashed`.
+pub fn canonical_hash<T>(obj: &T) -> sha256::Digest
+    where T: AsCanonical
+{
+    sha256::hash(&obj.as_canonical())
+}
+
+
+impl PublicKey for sign::PublicKey {
+    fn public_key(&self) -> &sign::PublicKey {
+        &self
+    }
+}
+
+impl SecretKey for sign::SecretKey {
+    fn secret_key(&self) -> &sign::SecretKey {
+        &self
+    }
+}
+
+
+#[must_use]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VerifyResult {
+    Valid,
+    Invalid,
+    Unsigned,
+    /// The key ID's algorithm isn't supported, e.g. verifying with a key whose
+    /// key ID isn't `ed25519:<version>`.
+    UnsupportedAlgorithm,
+}
+
+#[must_use]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum VerifyResultDetached {
+    Valid,
+    Invalid,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SigningKeyPair {
+    /// Public part of ED25519 signing key
+    pub public: sign::PublicKey,
+    /// Secret part of ED25519 signing key
+    pub secret: sign::SecretKey,
+    /// A unique ID for this signing key.
+    pub key_id: String,
+    pub entity: String,
+}
+
+
+impl SigningKeyPair {
+    /// Create the signing key from a standard ED25519 seed
+    pub fn from_seed<E, K>(seed: &[u8], entity: E, key_id: K) -> Result<SigningKeyPair, Error>
+        where E: Into<String>,
+              K: Into<String>
+    {
+        let seed = try!(sign::Seed::from_slice(seed).ok_or(Error::InvalidSeedLength {
+            expected: sign::SEEDBYTES,
+            found: seed.len(),
+        }));
+        let (public, secret) = sign::keypair_from_seed(&seed);
+        Ok(SigningKeyPair {
+            public: public,
+            secret: secret,
+            key_id: key_id.into(),
+            entity: entity.into(),
+        })
+    }
+
+    /// Generate a new random signing key.
+    pub fn generate<E, K>(entity: E, key_id: K) -> SigningKeyPair
+        where E: Into<String>,
+              K: Into<String>
+    {
+        let (public, secret) = sign::gen_keypair();
+        SigningKeyPair {
+            public: public,
+            secret: secret,
+            key_id: key_id.into(),
+            entity: entity.into(),
+        }
+    }
+
+    /// Parse a line of a signing key file, i.e. `ed25519 <version> <seed>`
+    /// where the seed is unpadded base64 and the key ID is
+    /// `ed25519:<version>`. Like Synapse, a seed with nonzero trailing bits
+    /// is accepted; `to_key_file_entry` writes it back canonically.
+    pub fn from_key_file_entry<E>(line: &str, entity: E) -> Result<SigningKeyPair, KeyFileError>
+        where E: Into<String>
+    {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() != 3 {
+            return Err(KeyFileError::Malformed);
+        }
+
+        let (algorithm, version, seed_b64) = (parts[0], parts[1], parts[2]);
+        if algorithm != ED25519 {
+            return Err(KeyFileError::UnsupportedAlgorithm(algorithm.to_string()));
+        }
+
+        let seed = try!(seed_b64.from_base64().map_err(|_| Error::Base64Decode));
+        Ok(try!(SigningKeyPair::from_seed(&seed, entity, format!("{}:{}", ED25519, version))))
+    }
+
+    /// Format the key as a line of a signing key file. See
+    /// `from_key_file_entry`. Fails if the key ID isn't of the form
+    /// `ed25519:<version>`, as the file has no way to record any other.
+    pub fn to_key_file_entry(&self) -> Result<String, KeyFileError> {
+        let prefix = format!("{}:", ED25519);
+        let invalid = || KeyFileError::InvalidKeyId(self.key_id.clone());
+        if !self.key_id.starts_with(&prefix) {
+            return Err(invalid());
+        }
+        let version = &self.key_id[prefix.len()..];
+        if version.is_empty() || version.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+        let seed = &self.secret.0[..sign::SEEDBYTES];
+        Ok(format!("{} {} {}", ED25519, version, seed.to_base64(UNPADDED_BASE64)))
+    }
+
+    /// Return a unpadded base64 version of the public key.
+    pub fn public_key_b64(&self) -> String {
+        self.public.0.to_base64(UNPADDED_BASE64)
+    }
+}
+
+impl NamedKey for SigningKeyPair {
+    fn entity(&self) -> &str {
+        &self.entity
+    }
+    fn key_id(&self) -> &str {
+        &self.key_id
+    }
+}
+
+impl SecretKey for SigningKeyPair {
+    fn secret_key(&self) -> &sign::SecretKey {
+        &self.secret
+    }
+}
+
+impl PublicKey for SigningKeyPair {
+    fn public_key(&self) -> &sign::PublicKey {
+        &self.public
+    }
+}
+
+
+/// The algorithm name used in signing key files and key IDs.
+const ED25519: &'static str = "ed25519";
+
+#[derive(Debug)]
+pub enum KeyFileError {
+    /// The line isn't of the form `<algorithm> <version> <seed>`.
+    Malformed,
+    UnsupportedAlgorithm(String),
+    /// The seed isn't valid base64 or has the wrong length.
+    InvalidSeed(Error),
+    /// The key ID can't be written to a key file, as it isn't of the form
+    /// `ed25519:<version>`.
+    InvalidKeyId(String),
+}
+
+impl From<Error> for KeyFileError {
+    fn from(err: Error) -> KeyFileError {
+        KeyFileError::InvalidSeed(err)
+    }
+}
+
+impl fmt::Display for KeyFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyFileError::Malformed => write!(f, "Malformed signing key file entry"),
+            KeyFileError::UnsupportedAlgorithm(ref algorithm) => {
+                write!(f, "Unsupported algorithm {}", algorithm)
+            }
+            KeyFileError::InvalidSeed(ref err) => write!(f, "Invalid seed: {}", err),
+            KeyFileError::InvalidKeyId(ref key_id) => {
+                write!(f, "Key ID {} isn't of the form ed25519:<version>", key_id)
+            }
+        }
+    }
+}
+
+impl error::Error for KeyFileError {
+    fn description(&self) -> &str {
+        match *self {
+            KeyFileError::Malformed => "malformed signing key file entry",
+            KeyFileError::UnsupportedAlgorithm(_) => "unsupported algorithm",
+            KeyFileError::InvalidSeed(_) => "invalid seed",
+            KeyFileError::InvalidKeyId(_) => "invalid key ID for a key file",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            KeyFileError::InvalidSeed(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Parse a signing key file, with one key per line. Blank lines are ignored.
+pub fn read_key_file<E>(contents: &str, entity: E) -> Result<Vec<SigningKeyPair>, KeyFileError>
+    where E: Into<String>
+{
+    let entity = entity.into();
+    contents.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| SigningKeyPair::from_key_file_entry(line, &entity[..]))
+            .collect()
+}
+
+/// Format the keys as a signing key file, with one key per line.
+pub fn write_key_file(keys: &[SigningKeyPair]) -> Result<String, KeyFileError> {
+    let mut contents = String::new();
+    for key in keys {
+        contents.push_str(&try!(key.to_key_file_entry()));
+        contents.push('\n');
+    }
+    Ok(contents)
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VerifyKey {
+    /// Public part of ED25519 signing key. Private so that it's always been
+    /// through `check_public_key`, see `PublicKey::public_key` to read it.
+    public: sign::PublicKey,
+    /// A unique ID for this key.
+    pub key_id: String,
+    pub entity: String,
+}
+
+impl VerifyKey {
+    /// Create the verify key from bytes. Small-order and non-canonical keys
+    /// are rejected, see `check_public_key`.
+    pub fn from_slice<E, K>(slice: &[u8], entity: E, key_id: K) -> Result<VerifyKey, Error>
+        where E: Into<String>,
+              K: Into<String>
+    {
+        let public_key = try!(sign::PublicKey::from_slice(slice).ok_or(Error::InvalidKeyLength {
+            expected: sign::PUBLICKEYBYTES,
+            found: slice.len(),
+        }));
+        try!(check_public_key(&public_key.0));
+        Ok(VerifyKey {
+            public: public_key,
+            entity: entity.into(),
+            key_id: key_id.into(),
+        })
+    }
+
+    /// Create the verfiy key from Base64 encoded bytes.
+    pub fn from_b64<E, K>(b64: &[u8], entity: E, key_id: K) -> Result<VerifyKey, Error>
+        where E: Into<String>,
+              K: Into<String>
+    {
+        let slice = try!(b64.from_base64().map_err(|_| Error::Base64Decode));
+        VerifyKey::from_slice(&slice, entity, key_id)
+    }
+
+    /// Create the verify key from raw bytes. Equivalent to `from_slice`.
+    pub fn from_raw_bytes<E, K>(bytes: &[u8], entity: E, key_id: K) -> Result<VerifyKey, Error>
+        where E: Into<String>,
+              K: Into<String>
+    {
+        VerifyKey::from_slice(bytes, entity, key_id)
+    }
+
+    /// Create the verify key from hex encoded bytes.
+    pub fn from_hex<E, K>(hex: &str, entity: E, key_id: K) -> Result<VerifyKey, Error>
+        where E: Into<String>,
+              K: Into<String>
+    {
+        let slice = try!(hex.from_hex().map_err(|_| Error::HexDecode));
+        VerifyKey::from_slice(&slice, entity, key_id)
+    }
+
+    /// Create the verify key from a multibase encoded ed25519 public key, i.e.
+    /// `z` followed by the base58btc encoding of the multicodec prefixed key.
+    pub fn from_multibase<E, K>(multibase: &str, entity: E, key_id: K) -> Result<VerifyKey, Error>
+        where E: Into<String>,
+              K: Into<String>
+    {
+        if !multibase.starts_with('z') {
+            return Err(Error::InvalidMultibase);
+        }
+
+        let bytes = try!(base58_decode(&multibase[1..]).ok_or(Error::InvalidMultibase));
+        if !bytes.starts_with(ED25519_MULTICODEC) {
+            return Err(Error::InvalidMultibase);
+        }
+        VerifyKey::from_slice(&bytes[ED25519_MULTICODEC.len()..], entity, key_id)
+    }
+
+    pub fn from_signing_key(signing_key: &SigningKeyPair) -> VerifyKey {
+        VerifyKey {
+            public: signing_key.public,
+            entity: signing_key.entity.clone(),
+            key_id: signing_key.key_id.clone(),
+        }
+    }
+
+    /// Return a unpadded base64 version of the public key.
+    pub fn public_key_b64(&self) -> String {
+        self.public.0.to_base64(UNPADDED_BASE64)
+    }
+
+    /// Return the raw bytes of the public key.
+    pub fn to_raw_bytes(&self) -> [u8; sign::PUBLICKEYBYTES] {
+        self.public.0
+    }
+
+    /// Return a lower case hex version of the public key.
+    pub fn to_hex(&self) -> String {
+        self.public.0.to_hex()
+    }
+
+    /// Return a multibase (base58btc) version of the multicodec prefixed
+    /// public key, as used by e.g. `did:key`.
+    pub fn to_multibase(&self) -> String {
+        let mut bytes = Vec::with_capacity(ED25519_MULTICODEC.len() + sign::PUBLICKEYBYTES);
+        bytes.extend_from_slice(ED25519_MULTICODEC);
+        bytes.extend_from_slice(&self.public.0);
+        format!("z{}", base58_encode(&bytes))
+    }
+}
+
+impl NamedKey for VerifyKey {
+    fn entity(&self) -> &str {
+        &self.entity
+    }
+    fn key_id(&self) -> &str {
+        &self.key_id
+    }
+}
+
+impl PublicKey for VerifyKey {
+    fn public_key(&self) -> &sign::PublicKey {
+        &self.public
+    }
+}
+
+
+/// The multicodec prefix of an ed25519 public key.
+const ED25519_MULTICODEC: &'static [u8] = &[0xed, 0x01];
+
+const BASE58_ALPHABET: &'static [u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+fn base58_encode(bytes: &[u8]) -> String {
+    let zeros = bytes.iter().take_while(|&&b| b == 0).count();
+
+    // Base 58 digits, least significant first.
+    let mut digits: Vec<u8> = Vec::with_capacity(bytes.len() * 138 / 100 + 1);
+    for &byte in &bytes[zeros..] {
+        let mut carry = byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let mut encoded = String::with_capacity(zeros + digits.len());
+    for _ in 0..zeros {
+        encoded.push('1');
+    }
+    for &digit in digits.iter().rev() {
+        encoded.push(BASE58_ALPHABET[digit as usize] as char);
+    }
+    encoded
+}
+
+fn base58_decode(encoded: &str) -> Option<Vec<u8>> {
+    let zeros = encoded.bytes().take_while(|&c| c == b'1').count();
+
+    // Decoded bytes, least significant first.
+    let mut bytes: Vec<u8> = Vec::with_capacity(encoded.len());
+    for c in encoded.bytes().skip(zeros) {
+        let mut carry = match BASE58_ALPHABET.iter().position(|&a| a == c) {
+            Some(value) => value as u32,
+            None => return None,
+        };
+        for byte in bytes.iter_mut() {
+            carry += (*byte as u32) * 58;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut decoded = vec![0u8; zeros];
+    decoded.extend(bytes.iter().rev());
+    Some(decoded)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+    use signed::{Signed, SimpleSigned};
+    use serde_json::Value;
+    use value::SignedValue;
+    use frozen::FrozenStruct;
+    use rustc_serialize::base64::{FromBase64, ToBase64};
+    use UNPADDED_BASE64;
+
+    type SimpleFrozen<'a> = FrozenStruct<'a, SimpleSigned, Value>;
+
+    #[test]
+    fn verify() {
+        let bytes = br#"{"old_verify_keys":{},"server_name":"jki.re","signatures":{"jki.re":{"ed25519:auto":"X2t7jN0jaJsiZWp57da9GqmQ874QFbukCMSqc5VclaB+2n4i8LPcZDkD6+fzg4tkfpSsiIDogkY4HWv1cnGhAg"}},"tls_fingerprints":[{"sha256":"Big0aXVWZ/m0oEcHddgP4hTriTEvb4Jx6592W1mB5i4"}],"valid_until_ts":1462110302047,"verify_keys":{"ed25519:auto":{"key":"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI"}}}"#;
+        let frozen: SimpleFrozen = FrozenStruct::from_slice(bytes).unwrap();
+
+        let key_b64 = b"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI";
+        let key = VerifyKey::from_b64(key_b64, "jki.re", "ed25519:auto").unwrap();
+        let key2 = VerifyKey::from_b64(key_b64, "example.com", "ed25519:auto").unwrap();
+        assert_eq!(key.verify(&frozen), VerifyResult::Valid);
+        assert_eq!(key2.verify(&frozen), VerifyResult::Unsigned);
+
+        let key3_b64 = b"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198Pna";
+        let key3 = VerifyKey::from_b64(key3_b64, "jki.re", "ed25519:auto").unwrap();
+        assert_eq!(key3.verify(&frozen), VerifyResult::Invalid);
+
+        let key4 = VerifyKey::from_b64(key_b64, "jki.re", "curve25519:auto").unwrap();
+        assert_eq!(key4.verify(&frozen), VerifyResult::UnsupportedAlgorithm);
+    }
+
+    #[test]
+    fn batch() {
+        let key_a = SigningKeyPair::generate("a.example", "ed25519:1");
+        let key_b = SigningKeyPair::generate("b.example", "ed25519:1");
+
+        let mut first: SimpleFrozen = FrozenStruct::from_slice(br#"{"a":1}"#).unwrap();
+        key_a.sign(&mut first);
+        key_b.sign(&mut first);
+        let mut second: SimpleFrozen = FrozenStruct::from_slice(br#"{"a":2}"#).unwrap();
+        key_a.sign(&mut second);
+        let wrong_sig = key_b.sign_detached(&first);
+        second.signatures_mut().add_signature("b.example", "ed25519:1", wrong_sig);
+
+        let verify_a = VerifyKey::from_signing_key(&key_a);
+        let verify_b = VerifyKey::from_signing_key(&key_b);
+        let mut unsupported = verify_a.clone();
+        unsupported.key_id = "curve25519:1".to_string();
+        let unknown = VerifyKey::from_signing_key(&SigningKeyPair::generate("c.example",
+                                                                            "ed25519:1"));
+
+        let results = verify_batch(&[(&first, &[&verify_a, &verify_b][..]),
+                                     (&second, &[&verify_a, &verify_b, &unknown][..]),
+                                     (&first, &[&unsupported][..])]);
+        assert_eq!(results,
+                   vec![vec![VerifyResult::Valid, VerifyResult::Valid],
+                        vec![VerifyResult::Valid, VerifyResult::Invalid, VerifyResult::Unsigned],
+                        vec![VerifyResult::UnsupportedAlgorithm]]);
+    }
+
+    #[test]
+    fn sign() {
+        let seed = "YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA1".from_base64().unwrap();
+        let sig_key = SigningKeyPair::from_seed(&seed, "domain", "ed25519:1").unwrap();
+
+        let mut frozen: SimpleFrozen = FrozenStruct::from_slice(b"{}").unwrap();
+        sig_key.sign(&mut frozen);
+
+        assert_eq!(
+            &frozen.serialize().unwrap()[..],
+            &br#"{"signatures":{"domain":{"ed25519:1":"K8280/U9SSy9IVtjBuVeLr+HpOB4BQFWbg+UZaADMtTdGYI7Geitb76LTrr5QV/7Xg4ahLwYGYZzuHGZKM5ZAQ"}}}"#[..]
+        );
+    }
+
+    #[test]
+    fn sign_hashed() {
+        let seed = "YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA1".from_base64().unwrap();
+        let sig_key = SigningKeyPair::from_seed(&seed, "domain", "ed25519:1").unwrap();
+
+        let mut frozen: SimpleFrozen = FrozenStruct::from_slice(b"{}").unwrap();
+        let hash = sig_key.sign_hashed(&mut frozen);
+
+        assert_eq!(hash.0.to_base64(UNPADDED_BASE64),
+                   "RBNvo1WzZ4oRRq0W9+hknpT7T8If536DEMBg9hyq/4o");
+        assert_eq!(hash, canonical_hash(&frozen));
+        assert!(frozen.signatures().get_signature("domain", "ed25519:1").is_some());
+    }
+
+    #[test]
+    fn key_formats() {
+        let key_b64 = b"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI";
+        let key = VerifyKey::from_b64(key_b64, "jki.re", "ed25519:auto").unwrap();
+
+        let hex = "4abfd58f7148ab24365a327d7d6a545d1773e9f5f8a050232ab0e6bb5f7c3e72";
+        let multibase = "z6MkjV3sfJKMBXAZAWz9iJaxQbXCnhdTA58tX6dtHpeJC8bT";
+
+        assert_eq!(key.to_hex(), hex);
+        assert_eq!(key.to_multibase(), multibase);
+
+        assert_eq!(VerifyKey::from_raw_bytes(&key.to_raw_bytes(), "jki.re", "ed25519:auto")
+                       .unwrap(),
+                   key);
+        assert_eq!(VerifyKey::from_hex(hex, "jki.re", "ed25519:auto").unwrap(), key);
+        assert_eq!(VerifyKey::from_multibase(multibase, "jki.re", "ed25519:auto").unwrap(),
+                   key);
+
+        assert!(VerifyKey::from_multibase(&multibase[1..], "jki.re", "ed25519:auto").is_err());
+        assert!(VerifyKey::from_multibase("z0OIl", "jki.re", "ed25519:auto").is_err());
+    }
+
+    #[test]
+    fn key_errors() {
+        match VerifyKey::from_b64(b"not base64!", "jki.re", "ed25519:auto") {
+            Err(Error::Base64Decode) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match VerifyKey::from_b64(b"AAAA", "jki.re", "ed25519:auto") {
+            Err(Error::InvalidKeyLength { expected: 32, found: 3 }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match VerifyKey::from_hex("zz", "jki.re", "ed25519:auto") {
+            Err(Error::HexDecode) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match SigningKeyPair::from_seed(b"short", "domain", "ed25519:1") {
+            Err(Error::InvalidSeedLength { expected: 32, found: 5 }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match VerifyKey::from_b64(b"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA",
+                                  "jki.re",
+                                  "ed25519:auto") {
+            Err(Error::SmallOrderKey) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match VerifyKey::from_hex("edffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff7f",
+                                  "jki.re",
+                                  "ed25519:auto") {
+            Err(Error::NonCanonicalKey) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn base58() {
+        assert_eq!(base58_encode(b""), "");
+        assert_eq!(base58_encode(b"\x00\x00\x01"), "112");
+        assert_eq!(base58_encode(b"hello world"), "StV1DL6CwTryKyV");
+        assert_eq!(base58_decode("StV1DL6CwTryKyV").unwrap(), b"hello world".to_vec());
+        assert_eq!(base58_decode("112").unwrap(), b"\x00\x00\x01".to_vec());
+    }
+
+    #[test]
+    fn key_file() {
+        let seed = "YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA0".from_base64().unwrap();
+        let expected = SigningKeyPair::from_seed(&seed, "domain", "ed25519:1").unwrap();
+
+        let line = "ed25519 1 YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA0";
+        let key = SigningKeyPair::from_key_file_entry(line, "domain").unwrap();
+        assert_eq!(key, expected);
+        assert_eq!(key.to_key_file_entry().unwrap(), line);
+
+        // Nonzero trailing bits are ignored and normalized on write.
+        let loose = "ed25519 1 YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA1";
+        let key = SigningKeyPair::from_key_file_entry(loose, "domain").unwrap();
+        assert_eq!(key, expected);
+        assert_eq!(key.to_key_file_entry().unwrap(), line);
+
+        let generated = SigningKeyPair::generate("domain", "ed25519:a_AbCd");
+        let contents = write_key_file(&[key.clone(), generated.clone()]).unwrap();
+        assert_eq!(read_key_file(&format!("{}\n\n", contents), "domain").unwrap(),
+                   vec![key, generated]);
+
+        match SigningKeyPair::from_key_file_entry("ed25519 1", "domain") {
+            Err(KeyFileError::Malformed) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match SigningKeyPair::from_key_file_entry("rsa 1 YJDB", "domain") {
+            Err(KeyFileError::UnsupportedAlgorithm(ref algorithm)) if algorithm == "rsa" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match SigningKeyPair::from_key_file_entry("ed25519 1 YJDB", "domain") {
+            Err(KeyFileError::InvalidSeed(Error::InvalidSeedLength { found: 3, .. })) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match SigningKeyPair::from_key_file_entry("ed25519 1 !!!!", "domain") {
+            Err(KeyFileError::InvalidSeed(Error::Base64Decode)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        for key_id in &["auto", "rsa:1", "ed25519:", "ed25519:a b"] {
+            let key = SigningKeyPair::from_seed(&seed, "domain", *key_id).unwrap();
+            match key.to_key_file_entry() {
+                Err(KeyFileError::InvalidKeyId(ref found)) if found == key_id => {}
+                res => panic!("Unexpected result for {}: {:?}", key_id, res),
+            }
+            match write_key_file(&[expected.clone(), key]) {
+                Err(KeyFileError::InvalidKeyId(ref found)) if found == key_id => {}
+                res => panic!("Unexpected result for {}: {:?}", key_id, res),
+            }
+        }
+    }
+
+    #[test]
+    fn synapse_key_file() {
+        // A key file in the format Synapse writes, with its public key and its
+        // signature of `{"a":1}` as computed by a separate ed25519
+        // implementation.
+        let contents = include_str!(concat!(env!("CARGO_MANIFEST_DIR"),
+                                            "/tests/fixtures/synapse.signing.key"));
+        let keys = read_key_file(contents, "example.com").unwrap();
+        assert_eq!(keys.len(), 1);
+        assert_eq!(keys[0].key_id, "ed25519:a_bYkQ");
+        assert_eq!(keys[0].public_key_b64(), "vqPKQ/hKUbmvBa8n6IbTm1o8nJadJvk4dFCSU0QsJvU");
+
+        let expected_sig = "O1ihKbaB6+6fCWUnMb8DFLdLZHdxVMGtBITReR+kZoYfYZgNF8Ut5RuO1QK/vz5OmeMQxcZXnOp7IEihGsr3DQ";
+        let mut value = SignedValue::from_slice(br#"{"a":1}"#).unwrap();
+        keys[0].sign(&mut value);
+        assert_eq!(value.signatures()
+                        .get_signature("example.com", "ed25519:a_bYkQ")
+                        .map(|sig| sig.0.to_base64(UNPADDED_BASE64)),
+                   Some(expected_sig.to_string()));
+
+        assert_eq!(write_key_file(&keys).unwrap(), contents);
+    }
+}
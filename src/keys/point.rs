@@ -0,0 +1,104 @@
+//! Rejecting ed25519 public keys that verifiers disagree on.
+//!
+//! A small-order key lets a forger produce signatures that verify for many
+//! messages, and whether a non-canonical encoding is accepted at all differs
+//! between libraries. Neither is produced by honest key generation.
+
+use error::Error;
+
+
+/// The encodings, ignoring the sign bit of x, of the points of order 1, 2, 4
+/// and 8. This is the same list libsodium uses.
+const SMALL_ORDER_POINTS: [[u8; 32]; 5] = [
+    // 0 (order 4)
+    [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+     0x00, 0x00],
+    // 1 (order 1)
+    [0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+     0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+     0x00, 0x00],
+    // Order 8
+    [0x26, 0xe8, 0x95, 0x8f, 0xc2, 0xb2, 0x27, 0xb0, 0x45, 0xc3, 0xf4, 0x89, 0xf2, 0xef, 0x98,
+     0xf0, 0xd5, 0xdf, 0xac, 0x05, 0xd3, 0xc6, 0x33, 0x39, 0xb1, 0x38, 0x02, 0x88, 0x6d, 0x53,
+     0xfc, 0x05],
+    // Order 8
+    [0xc7, 0x17, 0x6a, 0x70, 0x3d, 0x4d, 0xd8, 0x4f, 0xba, 0x3c, 0x0b, 0x76, 0x0d, 0x10, 0x67,
+     0x0f, 0x2a, 0x20, 0x53, 0xfa, 0x2c, 0x39, 0xcc, 0xc6, 0x4e, 0xc7, 0xfd, 0x77, 0x92, 0xac,
+     0x03, 0x7a],
+    // p - 1 (order 2)
+    [0xec, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+     0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+     0xff, 0x7f],
+];
+
+
+/// Check that a 32 byte key encodes y canonically, i.e. y < 2^255 - 19, and
+/// isn't a point of small order.
+pub fn check_public_key(key: &[u8; 32]) -> Result<(), Error> {
+    // y is the low 255 bits, so it's at least p iff they're all ones from bit
+    // 5 upwards and the low byte is at least 0xed.
+    if key[31] & 0x7f == 0x7f && key[1..31].iter().all(|&b| b == 0xff) && key[0] >= 0xed {
+        return Err(Error::NonCanonicalKey);
+    }
+
+    for point in &SMALL_ORDER_POINTS {
+        if key[..31] == point[..31] && key[31] & 0x7f == point[31] {
+            return Err(Error::SmallOrderKey);
+        }
+    }
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+    use keys::SigningKeyPair;
+
+    #[test]
+    fn small_order() {
+        for point in &SMALL_ORDER_POINTS {
+            let mut key = *point;
+            match check_public_key(&key) {
+                Err(Error::SmallOrderKey) => {}
+                res => panic!("Unexpected result: {:?}", res),
+            }
+            // With the sign of x flipped.
+            key[31] |= 0x80;
+            match check_public_key(&key) {
+                Err(Error::SmallOrderKey) => {}
+                res => panic!("Unexpected result: {:?}", res),
+            }
+        }
+    }
+
+    #[test]
+    fn non_canonical() {
+        let mut key = [0xff; 32];
+        key[31] = 0x7f;
+        for low in 0xed..0x100 {
+            key[0] = low as u8;
+            match check_public_key(&key) {
+                Err(Error::NonCanonicalKey) => {}
+                res => panic!("Unexpected result for {:x}: {:?}", low, res),
+            }
+        }
+
+        // p - 1 is canonical, but of order 2.
+        key[0] = 0xec;
+        match check_public_key(&key) {
+            Err(Error::SmallOrderKey) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn generated() {
+        for _ in 0..16 {
+            let key = SigningKeyPair::generate("domain", "ed25519:1");
+            assert!(check_public_key(&key.public.0).is_ok());
+        }
+    }
+}
@@ -0,0 +1,273 @@
+//! The set of signing keys an entity publishes, as served from
+//! `/_matrix/key/v2/server`.
+
+use std::fmt;
+use std::slice;
+
+use serde_json;
+
+use algorithm::key_id_namespace;
+use keys::{NamedSecretKey, ServerKeys, SigningKeyPair, VerifyKey};
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyRingError {
+    /// The signing key belongs to the given entity rather than the ring's.
+    WrongEntity(String),
+}
+
+impl fmt::Display for KeyRingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyRingError::WrongEntity(ref entity) => {
+                write!(f, "Signing key is for a different entity: {}", entity)
+            }
+        }
+    }
+}
+
+
+/// The current signing keys of an entity, along with the keys it has retired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyRing {
+    entity: String,
+    signing_keys: Vec<SigningKeyPair>,
+    /// Retired keys along with the time they expired, in milliseconds since
+    /// the epoch.
+    old_keys: Vec<(VerifyKey, u64)>,
+}
+
+impl KeyRing {
+    pub fn new<E>(entity: E) -> KeyRing
+        where E: Into<String>
+    {
+        KeyRing {
+            entity: entity.into(),
+            signing_keys: Vec::new(),
+            old_keys: Vec::new(),
+        }
+    }
+
+    pub fn entity(&self) -> &str {
+        &self.entity
+    }
+
+    pub fn signing_keys(&self) -> &[SigningKeyPair] {
+        &self.signing_keys
+    }
+
+    pub fn old_keys(&self) -> &[(VerifyKey, u64)] {
+        &self.old_keys
+    }
+
+    /// The current signing keys whose key IDs are in the namespace, e.g.
+    /// `service1` for `ed25519:service1__2024`.
+    pub fn signing_keys_in<'a>(&'a self, namespace: &'a str) -> SigningKeysIn<'a> {
+        SigningKeysIn {
+            keys: self.signing_keys.iter(),
+            namespace: namespace,
+        }
+    }
+
+    /// The retired keys whose key IDs are in the namespace.
+    pub fn old_keys_in<'a>(&'a self, namespace: &'a str) -> OldKeysIn<'a> {
+        OldKeysIn {
+            keys: self.old_keys.iter(),
+            namespace: namespace,
+        }
+    }
+
+    /// Add a signing key, replacing any with the same key ID. Fails, leaving
+    /// the ring untouched, if the key belongs to a different entity.
+    pub fn add_signing_key(&mut self, key: SigningKeyPair) -> Result<(), KeyRingError> {
+        try!(self.check_entity(&key));
+        self.signing_keys.retain(|k| k.key_id != key.key_id);
+        self.signing_keys.push(key);
+        Ok(())
+    }
+
+    fn check_entity(&self, key: &SigningKeyPair) -> Result<(), KeyRingError> {
+        if key.entity == self.entity {
+            Ok(())
+        } else {
+            Err(KeyRingError::WrongEntity(key.entity.clone()))
+        }
+    }
+
+    /// Stop signing with the key, publishing it as an old key that expired at
+    /// `expired_ts`. Returns false if there is no such signing key.
+    pub fn retire_key(&mut self, key_id: &str, expired_ts: u64) -> bool {
+        match self.signing_keys.iter().position(|k| k.key_id == key_id) {
+            Some(idx) => {
+                let key = self.signing_keys.remove(idx);
+                self.old_keys.push((VerifyKey::from_signing_key(&key), expired_ts));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop a signing or retired key entirely, so that it's no longer
+    /// published at all, e.g. because it was compromised.
+    pub fn remove_key(&mut self, key_id: &str) -> Option<VerifyKey> {
+        if let Some(idx) = self.signing_keys.iter().position(|k| k.key_id == key_id) {
+            return Some(VerifyKey::from_signing_key(&self.signing_keys.remove(idx)));
+        }
+        self.old_keys
+            .iter()
+            .position(|&(ref k, _)| k.key_id == key_id)
+            .map(|idx| self.old_keys.remove(idx).0)
+    }
+
+    /// Replace the current signing keys in the new key's namespace with it,
+    /// retiring them as of `expired_ts`. Keys in other namespaces are left
+    /// alone; un-namespaced keys are treated as a namespace of their own.
+    /// Returns the key IDs of the retired keys, or fails without retiring
+    /// anything if the key belongs to a different entity.
+    pub fn rotate_namespace(&mut self,
+                            key: SigningKeyPair,
+                            expired_ts: u64)
+                            -> Result<Vec<String>, KeyRingError> {
+        try!(self.check_entity(&key));
+
+        let retired: Vec<String> = {
+            let namespace = key_id_namespace(&key.key_id);
+            self.signing_keys
+                .iter()
+                .filter(|k| key_id_namespace(&k.key_id) == namespace)
+                .map(|k| k.key_id.clone())
+                .collect()
+        };
+
+        for key_id in &retired {
+            self.retire_key(key_id, expired_ts);
+        }
+        self.signing_keys.retain(|k| k.key_id != key.key_id);
+        self.signing_keys.push(key);
+
+        Ok(retired)
+    }
+
+    /// The keys as a server key response, signed by every current signing
+    /// key.
+    pub fn server_keys(&self, valid_until_ts: u64) -> ServerKeys {
+        let mut keys = ServerKeys::new(self.entity.clone(), valid_until_ts);
+        keys.verify_keys = self.signing_keys.iter().map(VerifyKey::from_signing_key).collect();
+        keys.old_verify_keys = self.old_keys.clone();
+        for key in &self.signing_keys {
+            key.sign(&mut keys);
+        }
+        keys
+    }
+
+    /// Build a server key response listing the keys, signed by every current
+    /// signing key.
+    pub fn server_key_response(&self, valid_until_ts: u64) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(&self.server_keys(valid_until_ts))
+    }
+}
+
+
+/// Iterator returned by `KeyRing::signing_keys_in`.
+pub struct SigningKeysIn<'a> {
+    keys: slice::Iter<'a, SigningKeyPair>,
+    namespace: &'a str,
+}
+
+impl<'a> Iterator for SigningKeysIn<'a> {
+    type Item = &'a SigningKeyPair;
+
+    fn next(&mut self) -> Option<&'a SigningKeyPair> {
+        let namespace = self.namespace;
+        self.keys.find(|k| key_id_namespace(&k.key_id) == Some(namespace))
+    }
+}
+
+/// Iterator returned by `KeyRing::old_keys_in`.
+pub struct OldKeysIn<'a> {
+    keys: slice::Iter<'a, (VerifyKey, u64)>,
+    namespace: &'a str,
+}
+
+impl<'a> Iterator for OldKeysIn<'a> {
+    type Item = &'a (VerifyKey, u64);
+
+    fn next(&mut self) -> Option<&'a (VerifyKey, u64)> {
+        let namespace = self.namespace;
+        self.keys.find(|&&(ref k, _)| key_id_namespace(&k.key_id) == Some(namespace))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{SigningKeyPair, validate_fetched_key_response};
+
+    #[test]
+    fn response() {
+        let mut ring = KeyRing::new("example.com");
+        ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:a")).unwrap();
+        ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:b")).unwrap();
+        assert!(ring.retire_key("ed25519:a", 1000));
+        assert!(!ring.retire_key("ed25519:a", 1000));
+
+        let response = ring.server_key_response(5000).unwrap();
+        let keys = validate_fetched_key_response(&response, "example.com", 2000, 4000).unwrap();
+
+        assert_eq!(keys.valid_until_ts, 5000);
+        assert_eq!(keys.verify_keys.len(), 1);
+        assert_eq!(keys.verify_keys[0].key_id, "ed25519:b");
+        assert_eq!(keys.old_verify_keys.len(), 1);
+        assert_eq!(keys.old_verify_keys[0].0.key_id, "ed25519:a");
+        assert_eq!(keys.old_verify_keys[0].1, 1000);
+    }
+
+    #[test]
+    fn namespaces() {
+        let mut ring = KeyRing::new("example.com");
+        ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:auto")).unwrap();
+        ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:a_HDhS")).unwrap();
+        ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:service1__2023"))
+            .unwrap();
+        ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:service2__2023"))
+            .unwrap();
+
+        let service1: Vec<&str> = ring.signing_keys_in("service1")
+                                      .map(|k| &k.key_id[..])
+                                      .collect();
+        assert_eq!(service1, vec!["ed25519:service1__2023"]);
+        // A Synapse style key ID isn't in a namespace called `a`.
+        assert_eq!(ring.signing_keys_in("a").count(), 0);
+
+        let retired = ring.rotate_namespace(SigningKeyPair::generate("example.com",
+                                                                     "ed25519:service1__2024"),
+                                            1000)
+                          .unwrap();
+        assert_eq!(retired, vec!["ed25519:service1__2023".to_string()]);
+
+        let service1: Vec<&str> = ring.signing_keys_in("service1")
+                                      .map(|k| &k.key_id[..])
+                                      .collect();
+        assert_eq!(service1, vec!["ed25519:service1__2024"]);
+        assert_eq!(ring.signing_keys_in("service2").count(), 1);
+        assert_eq!(ring.old_keys_in("service1").count(), 1);
+        assert_eq!(ring.old_keys_in("service2").count(), 0);
+        assert_eq!(ring.signing_keys().len(), 4);
+    }
+
+    #[test]
+    fn wrong_entity() {
+        let mut ring = KeyRing::new("example.com");
+        ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:service1__1"))
+            .unwrap();
+        let before = ring.clone();
+
+        let other = SigningKeyPair::generate("example.org", "ed25519:service1__2");
+        assert_eq!(ring.add_signing_key(other.clone()),
+                   Err(KeyRingError::WrongEntity("example.org".to_string())));
+        assert_eq!(ring.rotate_namespace(other, 1000),
+                   Err(KeyRingError::WrongEntity("example.org".to_string())));
+        assert_eq!(ring, before);
+    }
+}
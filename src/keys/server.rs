@@ -0,0 +1,568 @@
+//! Server key responses, as served from `/_matrix/key/v2/server`, and their
+//! validation.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+
+use serde;
+use serde_json::{self, Value};
+use sodiumoxide::crypto::sign;
+
+use error::Error;
+use keys::VerifyKey;
+use ser::encode_canonically;
+use ser::signatures::SignatureMap;
+use signed::{AsCanonical, Signed, SignedMut};
+
+
+#[derive(Debug)]
+pub enum KeyResponseError {
+    Json(serde_json::Error),
+    /// A required field is missing or has the wrong type.
+    MissingField(&'static str),
+    /// The `server_name` doesn't match the server the keys were fetched from.
+    ServerNameMismatch(String),
+    /// The response is no longer valid.
+    Expired(u64),
+    /// The response isn't valid for as long as required.
+    ValidityTooShort(u64),
+    /// The key with the given key ID couldn't be decoded.
+    InvalidKey(String, Error),
+    /// Part of the response, e.g. a signature, couldn't be decoded.
+    Decode(Error),
+    /// The response isn't signed by any of its own keys.
+    Unsigned,
+    /// A self-signature didn't verify.
+    InvalidSignature(String),
+    /// The response is signed by the server with a key that isn't one of its
+    /// current keys.
+    UnknownKey(String),
+    /// The server has failed too often recently, and won't be tried again
+    /// until the given time. See `NegativeCache::validate_key_response`.
+    Blocked(u64),
+}
+
+impl From<serde_json::Error> for KeyResponseError {
+    fn from(err: serde_json::Error) -> KeyResponseError {
+        KeyResponseError::Json(err)
+    }
+}
+
+impl From<Error> for KeyResponseError {
+    fn from(err: Error) -> KeyResponseError {
+        match err {
+            Error::Json(err) => KeyResponseError::Json(err),
+            err => KeyResponseError::Decode(err),
+        }
+    }
+}
+
+impl fmt::Display for KeyResponseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            KeyResponseError::Json(ref err) => write!(f, "Invalid JSON: {}", err),
+            KeyResponseError::MissingField(field) => write!(f, "Missing or invalid {}", field),
+            KeyResponseError::ServerNameMismatch(ref name) => {
+                write!(f, "Response is for a different server: {}", name)
+            }
+            KeyResponseError::Expired(ts) => write!(f, "Response expired at {}", ts),
+            KeyResponseError::ValidityTooShort(ts) => {
+                write!(f, "Response is only valid until {}", ts)
+            }
+            KeyResponseError::InvalidKey(ref key_id, ref err) => {
+                write!(f, "Invalid key {}: {}", key_id, err)
+            }
+            KeyResponseError::Decode(ref err) => write!(f, "{}", err),
+            KeyResponseError::Unsigned => write!(f, "Response isn't signed by its own keys"),
+            KeyResponseError::InvalidSignature(ref key_id) => {
+                write!(f, "Invalid signature by {}", key_id)
+            }
+            KeyResponseError::UnknownKey(ref key_id) => {
+                write!(f, "Signed by {}, which isn't a current key", key_id)
+            }
+            KeyResponseError::Blocked(until) => write!(f, "Server is blocked until {}", until),
+        }
+    }
+}
+
+impl error::Error for KeyResponseError {
+    fn description(&self) -> &str {
+        match *self {
+            KeyResponseError::Json(_) => "invalid JSON",
+            KeyResponseError::MissingField(_) => "missing or invalid field",
+            KeyResponseError::ServerNameMismatch(_) => "response is for a different server",
+            KeyResponseError::Expired(_) => "response expired",
+            KeyResponseError::ValidityTooShort(_) => "response validity too short",
+            KeyResponseError::InvalidKey(..) => "invalid key",
+            KeyResponseError::Decode(ref err) => error::Error::description(err),
+            KeyResponseError::Unsigned => "response isn't self-signed",
+            KeyResponseError::InvalidSignature(_) => "invalid signature",
+            KeyResponseError::UnknownKey(_) => "signed by an unknown key",
+            KeyResponseError::Blocked(_) => "server is blocked",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            KeyResponseError::Json(ref err) => Some(err),
+            KeyResponseError::InvalidKey(_, ref err) => Some(err),
+            KeyResponseError::Decode(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+
+/// A server's published keys.
+///
+/// Fields that aren't modelled are kept in `other`, so that the canonical form
+/// of a parsed response matches what the server signed.
+#[derive(Debug, Clone)]
+pub struct ServerKeys {
+    pub server_name: String,
+    pub valid_until_ts: u64,
+    pub verify_keys: Vec<VerifyKey>,
+    /// Keys the server no longer uses, along with when they expired.
+    pub old_verify_keys: Vec<(VerifyKey, u64)>,
+    /// The hashes of the server's TLS certificates, which older servers
+    /// include.
+    pub tls_fingerprints: Option<Vec<BTreeMap<String, String>>>,
+    pub other: BTreeMap<String, Value>,
+    pub signatures: SignatureMap,
+    /// Whether `old_verify_keys` is included even if it's empty. It's
+    /// optional, so a response may have been signed without it.
+    include_old_verify_keys: bool,
+    /// The canonical form of a parsed response as received, which its
+    /// self-signatures are checked against, as the fields above may not
+    /// reproduce it exactly.
+    received: Option<Vec<u8>>,
+}
+
+impl PartialEq for ServerKeys {
+    fn eq(&self, other: &ServerKeys) -> bool {
+        self.server_name == other.server_name && self.valid_until_ts == other.valid_until_ts &&
+        self.verify_keys == other.verify_keys &&
+        self.old_verify_keys == other.old_verify_keys &&
+        self.tls_fingerprints == other.tls_fingerprints && self.other == other.other &&
+        self.signatures == other.signatures &&
+        self.include_old_verify_keys == other.include_old_verify_keys
+    }
+}
+
+/// The keys from a validated server key response.
+pub type FetchedServerKeys = ServerKeys;
+
+impl ServerKeys {
+    pub fn new<S>(server_name: S, valid_until_ts: u64) -> ServerKeys
+        where S: Into<String>
+    {
+        ServerKeys {
+            server_name: server_name.into(),
+            valid_until_ts: valid_until_ts,
+            verify_keys: Vec::new(),
+            old_verify_keys: Vec::new(),
+            tls_fingerprints: None,
+            other: BTreeMap::new(),
+            signatures: SignatureMap::new(),
+            include_old_verify_keys: true,
+            received: None,
+        }
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> Result<ServerKeys, KeyResponseError> {
+        ServerKeys::from_value(try!(serde_json::from_slice(bytes)))
+    }
+
+    pub fn from_value(val: Value) -> Result<ServerKeys, KeyResponseError> {
+        let received = try!(encode_canonically(&val));
+        let mut obj = match val {
+            Value::Object(obj) => obj,
+            _ => return Err(KeyResponseError::MissingField("server_name")),
+        };
+        obj.remove("unsigned");
+
+        let signatures = match obj.remove("signatures") {
+            Some(sigs) => try!(serde_json::from_value(sigs)),
+            None => SignatureMap::new(),
+        };
+        let server_name = try!(obj.remove("server_name")
+                                  .and_then(|v| v.as_str().map(String::from))
+                                  .ok_or(KeyResponseError::MissingField("server_name")));
+        let valid_until_ts = try!(obj.remove("valid_until_ts")
+                                     .and_then(|v| v.as_u64())
+                                     .ok_or(KeyResponseError::MissingField("valid_until_ts")));
+
+        let verify_keys = try!(parse_keys(obj.remove("verify_keys"), "verify_keys", &server_name))
+                              .into_iter()
+                              .map(|(key, _)| key)
+                              .collect();
+
+        let old_keys_val = obj.remove("old_verify_keys");
+        let include_old_verify_keys = old_keys_val.is_some();
+        let mut old_verify_keys = Vec::new();
+        if include_old_verify_keys {
+            for (key, expired_ts) in try!(parse_keys(old_keys_val,
+                                                     "old_verify_keys",
+                                                     &server_name)) {
+                match expired_ts {
+                    Some(expired_ts) => old_verify_keys.push((key, expired_ts)),
+                    None => return Err(KeyResponseError::MissingField("expired_ts")),
+                }
+            }
+        }
+
+        let tls_fingerprints = match obj.remove("tls_fingerprints") {
+            Some(fingerprints) => Some(try!(serde_json::from_value(fingerprints))),
+            None => None,
+        };
+
+        Ok(ServerKeys {
+            server_name: server_name,
+            valid_until_ts: valid_until_ts,
+            verify_keys: verify_keys,
+            old_verify_keys: old_verify_keys,
+            tls_fingerprints: tls_fingerprints,
+            other: obj,
+            signatures: signatures,
+            include_old_verify_keys: include_old_verify_keys,
+            received: Some(received),
+        })
+    }
+
+    /// The response without its signatures.
+    fn content(&self) -> Value {
+        let mut obj = self.other.clone();
+        obj.insert("server_name".to_string(),
+                   Value::String(self.server_name.clone()));
+        obj.insert("valid_until_ts".to_string(), Value::U64(self.valid_until_ts));
+        obj.insert("verify_keys".to_string(),
+                   Value::Object(self.verify_keys
+                                     .iter()
+                                     .map(|key| (key.key_id.clone(), key_object(key, None)))
+                                     .collect()));
+        if self.include_old_verify_keys || !self.old_verify_keys.is_empty() {
+            obj.insert("old_verify_keys".to_string(),
+                       Value::Object(self.old_verify_keys
+                                         .iter()
+                                         .map(|&(ref key, expired_ts)| {
+                                             (key.key_id.clone(), key_object(key, Some(expired_ts)))
+                                         })
+                                         .collect()));
+        }
+        if let Some(ref fingerprints) = self.tls_fingerprints {
+            obj.insert("tls_fingerprints".to_string(), serde_json::to_value(fingerprints));
+        }
+        Value::Object(obj)
+    }
+
+    /// A current or old key by key ID.
+    pub fn get_key(&self, key_id: &str) -> Option<&VerifyKey> {
+        self.verify_keys
+            .iter()
+            .find(|key| key.key_id == key_id)
+            .or_else(|| {
+                self.old_verify_keys
+                    .iter()
+                    .find(|&&(ref key, _)| key.key_id == key_id)
+                    .map(|&(ref key, _)| key)
+            })
+    }
+
+    /// The key to verify an object made at `ts` with, if the key was in use
+    /// then: current keys up to `valid_until_ts`, and old keys up to when they
+    /// expired.
+    pub fn key_valid_at(&self, key_id: &str, ts: u64) -> Option<&VerifyKey> {
+        if let Some(key) = self.verify_keys.iter().find(|key| key.key_id == key_id) {
+            return if ts <= self.valid_until_ts { Some(key) } else { None };
+        }
+        self.old_verify_keys
+            .iter()
+            .find(|&&(ref key, expired_ts)| key.key_id == key_id && ts < expired_ts)
+            .map(|&(ref key, _)| key)
+    }
+
+    /// Check that the response is signed by at least one of its current keys,
+    /// that every such signature is valid, and that the server hasn't signed it
+    /// with any other key. A parsed response is checked as it was received.
+    pub fn check_self_signatures(&self) -> Result<(), KeyResponseError> {
+        let canonical = match self.received {
+            Some(ref received) => Cow::Borrowed(&received[..]),
+            None => self.as_canonical(),
+        };
+
+        let mut verified = false;
+        for (key_id, sig) in self.signatures.get_signatures_for_entity(&self.server_name) {
+            let key = match self.verify_keys.iter().find(|key| key.key_id == key_id) {
+                Some(key) => key,
+                None => return Err(KeyResponseError::UnknownKey(key_id.to_string())),
+            };
+            if !sign::verify_detached(sig, &canonical, &key.public) {
+                return Err(KeyResponseError::InvalidSignature(key_id.to_string()));
+            }
+            verified = true;
+        }
+        if verified {
+            Ok(())
+        } else {
+            Err(KeyResponseError::Unsigned)
+        }
+    }
+
+    /// Check that the response is for `claimed_server`, that it's valid at
+    /// `now` and until at least `minimum_valid_until`, and that it's signed by
+    /// its own keys.
+    pub fn validate(&self,
+                    claimed_server: &str,
+                    now: u64,
+                    minimum_valid_until: u64)
+                    -> Result<(), KeyResponseError> {
+        if self.server_name != claimed_server {
+            return Err(KeyResponseError::ServerNameMismatch(self.server_name.clone()));
+        }
+        if self.valid_until_ts < now {
+            return Err(KeyResponseError::Expired(self.valid_until_ts));
+        }
+        if self.valid_until_ts < minimum_valid_until {
+            return Err(KeyResponseError::ValidityTooShort(self.valid_until_ts));
+        }
+        self.check_self_signatures()
+    }
+}
+
+impl Signed for ServerKeys {
+    fn signatures(&self) -> &SignatureMap {
+        &self.signatures
+    }
+}
+
+impl SignedMut for ServerKeys {
+    fn signatures_mut(&mut self) -> &mut SignatureMap {
+        &mut self.signatures
+    }
+}
+
+impl AsCanonical for ServerKeys {
+    fn as_canonical(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_canonically(&self.content()).expect("JSON values always serialize"))
+    }
+}
+
+impl serde::Serialize for ServerKeys {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        let mut val = self.content();
+        if let Some(obj) = val.as_object_mut() {
+            obj.insert("signatures".to_string(), serde_json::to_value(&self.signatures));
+        }
+        serde::Serialize::serialize(&val, serializer)
+    }
+}
+
+impl serde::Deserialize for ServerKeys {
+    fn deserialize<D>(deserializer: &mut D) -> Result<ServerKeys, D::Error>
+        where D: serde::Deserializer
+    {
+        let val: Value = try!(serde::Deserialize::deserialize(deserializer));
+        ServerKeys::from_value(val).map_err(|e| serde::de::Error::invalid_value(&e.to_string()))
+    }
+}
+
+
+/// Validate a server key response fetched from `claimed_server`, checking that
+/// the server name matches, that the response is signed by its own keys and
+/// that it's valid at `now` and until at least `minimum_valid_until`.
+///
+/// Timestamps are in milliseconds since the epoch.
+pub fn validate_fetched_key_response(bytes: &[u8],
+                                     claimed_server: &str,
+                                     now: u64,
+                                     minimum_valid_until: u64)
+                                     -> Result<ServerKeys, KeyResponseError> {
+    let keys = try!(ServerKeys::from_slice(bytes));
+    try!(keys.validate(claimed_server, now, minimum_valid_until));
+    Ok(keys)
+}
+
+fn key_object(key: &VerifyKey, expired_ts: Option<u64>) -> Value {
+    let mut obj = BTreeMap::new();
+    obj.insert("key".to_string(), Value::String(key.public_key_b64()));
+    if let Some(expired_ts) = expired_ts {
+        obj.insert("expired_ts".to_string(), Value::U64(expired_ts));
+    }
+    Value::Object(obj)
+}
+
+/// Parse a map of key ID to key object, returning each key with its
+/// `expired_ts`, if any.
+fn parse_keys(val: Option<Value>,
+              field: &'static str,
+              server_name: &str)
+              -> Result<Vec<(VerifyKey, Option<u64>)>, KeyResponseError> {
+    let keys = match val {
+        Some(Value::Object(keys)) => keys,
+        _ => return Err(KeyResponseError::MissingField(field)),
+    };
+
+    let mut parsed = Vec::with_capacity(keys.len());
+    for (key_id, key_obj) in keys {
+        let b64 = try!(key_obj.find("key")
+                              .and_then(|v| v.as_str())
+                              .ok_or(KeyResponseError::MissingField("key")));
+        let key = try!(VerifyKey::from_b64(b64.as_bytes(), server_name, &key_id[..])
+                           .map_err(|err| KeyResponseError::InvalidKey(key_id.clone(), err)));
+        let expired_ts = key_obj.find("expired_ts").and_then(|v| v.as_u64());
+        parsed.push((key, expired_ts));
+    }
+    Ok(parsed)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+    use keys::{SecretKey, SigningKeyPair, VerifyKey};
+    use serde_json;
+    use value::SignedValue;
+
+    const RESPONSE: &'static [u8] = br#"{"old_verify_keys":{},"server_name":"jki.re","signatures":{"jki.re":{"ed25519:auto":"X2t7jN0jaJsiZWp57da9GqmQ874QFbukCMSqc5VclaB+2n4i8LPcZDkD6+fzg4tkfpSsiIDogkY4HWv1cnGhAg"}},"tls_fingerprints":[{"sha256":"Big0aXVWZ/m0oEcHddgP4hTriTEvb4Jx6592W1mB5i4"}],"valid_until_ts":1462110302047,"verify_keys":{"ed25519:auto":{"key":"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI"}}}"#;
+    const NOW: u64 = 1462000000000;
+
+    #[test]
+    fn valid() {
+        let keys = validate_fetched_key_response(RESPONSE, "jki.re", NOW, NOW + 3600 * 1000)
+                       .unwrap();
+
+        assert_eq!(keys.server_name, "jki.re");
+        assert_eq!(keys.valid_until_ts, 1462110302047);
+        assert_eq!(keys.verify_keys.len(), 1);
+        assert_eq!(keys.verify_keys[0].entity, "jki.re");
+        assert_eq!(keys.verify_keys[0].key_id, "ed25519:auto");
+        assert_eq!(keys.verify_keys[0].public_key_b64(),
+                   "Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI");
+        assert!(keys.old_verify_keys.is_empty());
+    }
+
+    #[test]
+    fn invalid() {
+        match validate_fetched_key_response(RESPONSE, "example.com", NOW, NOW) {
+            Err(KeyResponseError::ServerNameMismatch(ref name)) if name == "jki.re" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        match validate_fetched_key_response(RESPONSE, "jki.re", 1462110302048, 0) {
+            Err(KeyResponseError::Expired(1462110302047)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        match validate_fetched_key_response(RESPONSE, "jki.re", NOW, 1462110302048) {
+            Err(KeyResponseError::ValidityTooShort(1462110302047)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let tampered = String::from_utf8(RESPONSE.to_vec()).unwrap().replace("1462110302047",
+                                                                             "1462110302048");
+        match validate_fetched_key_response(tampered.as_bytes(), "jki.re", NOW, NOW) {
+            Err(KeyResponseError::InvalidSignature(ref key_id)) if key_id == "ed25519:auto" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let unknown = String::from_utf8(RESPONSE.to_vec()).unwrap().replace("ed25519:auto\":\"X2",
+                                                                            "ed25519:other\":\"X2");
+        match validate_fetched_key_response(unknown.as_bytes(), "jki.re", NOW, NOW) {
+            Err(KeyResponseError::UnknownKey(ref key_id)) if key_id == "ed25519:other" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let bad_key = String::from_utf8(RESPONSE.to_vec()).unwrap().replace("\"key\":\"Sr/Vj3",
+                                                                            "\"key\":\"Sr/Vj");
+        match validate_fetched_key_response(bad_key.as_bytes(), "jki.re", NOW, NOW) {
+            Err(KeyResponseError::InvalidKey(ref key_id, Error::InvalidKeyLength { found: 31, .. }))
+                if key_id == "ed25519:auto" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let unsigned = String::from_utf8(RESPONSE.to_vec()).unwrap().replace("{\"jki.re\":{",
+                                                                             "{\"notary\":{");
+        match validate_fetched_key_response(unsigned.as_bytes(), "jki.re", NOW, NOW) {
+            Err(KeyResponseError::Unsigned) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // A valid signature by a current key doesn't excuse one by another key.
+        let mut keys = ServerKeys::from_slice(RESPONSE).unwrap();
+        let sig = *keys.signatures.get_signature("jki.re", "ed25519:auto").unwrap();
+        keys.signatures.add_signature("jki.re", "ed25519:old", sig);
+        match keys.check_self_signatures() {
+            Err(KeyResponseError::UnknownKey(ref key_id)) if key_id == "ed25519:old" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn server_keys() {
+        let keys: ServerKeys = serde_json::from_slice(RESPONSE).unwrap();
+        assert_eq!(keys.tls_fingerprints.as_ref().map(|f| f.len()), Some(1));
+        assert!(keys.check_self_signatures().is_ok());
+        assert_eq!(serde_json::to_vec(&keys).unwrap(), RESPONSE.to_vec());
+
+        assert!(keys.get_key("ed25519:auto").is_some());
+        assert!(keys.key_valid_at("ed25519:auto", 1462110302047).is_some());
+        assert!(keys.key_valid_at("ed25519:auto", 1462110302048).is_none());
+        assert!(keys.key_valid_at("ed25519:other", NOW).is_none());
+
+        // Without `old_verify_keys` the response still round trips.
+        let without_old = String::from_utf8(RESPONSE.to_vec())
+                              .unwrap()
+                              .replace(r#""old_verify_keys":{},"#, "");
+        let parsed = ServerKeys::from_slice(without_old.as_bytes()).unwrap();
+        assert_eq!(serde_json::to_string(&parsed).unwrap(), without_old);
+    }
+
+    #[test]
+    fn received_form() {
+        // Fields inside key objects and padded keys aren't modelled, but the
+        // signature still covers them.
+        let key = SigningKeyPair::generate("example.com", "ed25519:1");
+        let json = format!(r#"{{"server_name":"example.com","valid_until_ts":{},
+                               "verify_keys":{{"ed25519:1":{{"key":"{}=","x":1}}}}}}"#,
+                           NOW,
+                           key.public_key_b64());
+        let mut value = SignedValue::from_slice(json.as_bytes()).unwrap();
+        let sig = key.sign_detached(&value);
+        value.signatures_mut().add_signature("example.com", "ed25519:1", sig);
+        let bytes = value.to_vec().unwrap();
+
+        let keys = validate_fetched_key_response(&bytes, "example.com", NOW, NOW).unwrap();
+        assert_eq!(keys.verify_keys[0].public_key_b64(), key.public_key_b64());
+        assert!(&keys.as_canonical()[..] != &value.as_canonical()[..]);
+    }
+
+    #[test]
+    fn old_keys() {
+        let mut keys = ServerKeys::new("example.com", 5000);
+        let current = VerifyKey::from_b64(b"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI",
+                                          "example.com",
+                                          "ed25519:b")
+                          .unwrap();
+        let mut old = current.clone();
+        old.key_id = "ed25519:a".to_string();
+        keys.verify_keys.push(current);
+        keys.old_verify_keys.push((old, 1000));
+
+        assert!(keys.key_valid_at("ed25519:a", 999).is_some());
+        assert!(keys.key_valid_at("ed25519:a", 1000).is_none());
+        assert!(keys.key_valid_at("ed25519:b", 5000).is_some());
+
+        match keys.check_self_signatures() {
+            Err(KeyResponseError::Unsigned) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let reparsed = ServerKeys::from_slice(&serde_json::to_vec(&keys).unwrap()).unwrap();
+        assert_eq!(reparsed, keys);
+    }
+}
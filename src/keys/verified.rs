@@ -0,0 +1,201 @@
+//! Deserializing signed objects only once their signature has been checked.
+
+use std::error;
+use std::fmt;
+
+use serde::Deserialize;
+use serde_json;
+
+use error::Error;
+use keys::{NamedPublicKey, VerifyResult};
+use ser::{CanonicalError, CanonicalOptions};
+use value::SignedValue;
+
+
+#[derive(Debug)]
+pub enum VerifyError {
+    Json(serde_json::Error),
+    /// The input was refused by the canonicalization options.
+    Canonical(CanonicalError),
+    /// A key or signature couldn't be decoded.
+    Decode(Error),
+    /// The object has no signature from the key.
+    Unsigned,
+    /// The key's signature didn't verify.
+    Invalid,
+    /// The key is for an algorithm that isn't supported.
+    UnsupportedAlgorithm,
+}
+
+impl From<serde_json::Error> for VerifyError {
+    fn from(err: serde_json::Error) -> VerifyError {
+        VerifyError::Json(err)
+    }
+}
+
+impl From<CanonicalError> for VerifyError {
+    fn from(err: CanonicalError) -> VerifyError {
+        match err {
+            CanonicalError::Json(err) => VerifyError::Json(err),
+            err => VerifyError::Canonical(err),
+        }
+    }
+}
+
+impl From<Error> for VerifyError {
+    fn from(err: Error) -> VerifyError {
+        match err {
+            Error::Json(err) => VerifyError::Json(err),
+            Error::Canonical(err) => VerifyError::Canonical(err),
+            err => VerifyError::Decode(err),
+        }
+    }
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VerifyError::Json(ref err) => write!(f, "Invalid JSON: {}", err),
+            VerifyError::Canonical(ref err) => write!(f, "{}", err),
+            VerifyError::Decode(ref err) => write!(f, "{}", err),
+            _ => write!(f, "Verification failed: {}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for VerifyError {
+    fn description(&self) -> &str {
+        match *self {
+            VerifyError::Json(_) => "invalid JSON",
+            VerifyError::Canonical(ref err) => error::Error::description(err),
+            VerifyError::Decode(ref err) => error::Error::description(err),
+            VerifyError::Unsigned => "not signed by the key",
+            VerifyError::Invalid => "invalid signature",
+            VerifyError::UnsupportedAlgorithm => "unsupported algorithm",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            VerifyError::Json(ref err) => Some(err),
+            VerifyError::Canonical(ref err) => Some(err),
+            VerifyError::Decode(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+
+/// Parse a signed object and verify it against `key`, only deserializing it
+/// into `T` if the signature is valid.
+///
+/// `T` is deserialized from the signed content only, i.e. without
+/// `signatures` and `unsigned`, so that nothing the signature doesn't cover
+/// ends up in it.
+pub fn verified_from_slice<T, K>(bytes: &[u8], key: &K) -> Result<T, VerifyError>
+    where T: Deserialize,
+          K: NamedPublicKey
+{
+    let value = try!(SignedValue::from_slice(bytes));
+    deserialize_verified(value, key)
+}
+
+/// Like `verified_from_slice`, but parses the input with `options`, e.g. to
+/// choose how non-finite numbers are handled.
+pub fn verified_from_slice_with<T, K>(bytes: &[u8],
+                                      key: &K,
+                                      options: &CanonicalOptions)
+                                      -> Result<T, VerifyError>
+    where T: Deserialize,
+          K: NamedPublicKey
+{
+    let value = try!(SignedValue::from_slice_with(bytes, options));
+    deserialize_verified(value, key)
+}
+
+fn deserialize_verified<T, K>(value: SignedValue, key: &K) -> Result<T, VerifyError>
+    where T: Deserialize,
+          K: NamedPublicKey
+{
+    match key.verify(&value) {
+        VerifyResult::Valid => Ok(try!(serde_json::from_value(value.content().clone()))),
+        VerifyResult::Invalid => Err(VerifyError::Invalid),
+        VerifyResult::Unsigned => Err(VerifyError::Unsigned),
+        VerifyResult::UnsupportedAlgorithm => Err(VerifyError::UnsupportedAlgorithm),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{NamedSecretKey, SigningKeyPair, VerifyKey};
+    use ser::{CanonicalError, CanonicalOptions, NonFinitePolicy};
+    use serde_json::{self, Value};
+    use std::collections::BTreeMap;
+    use value::SignedValue;
+
+    type Message = BTreeMap<String, Value>;
+
+    #[test]
+    fn verified() {
+        let key = SigningKeyPair::generate("domain", "ed25519:1");
+        let verify_key = VerifyKey::from_signing_key(&key);
+
+        let mut value = SignedValue::from_slice(br#"{"body":"hello"}"#).unwrap();
+        key.sign(&mut value);
+        value.set_unsigned(Some(serde_json::from_str(r#"{"body":"injected"}"#).unwrap()));
+        let bytes = value.to_vec().unwrap();
+
+        let message: Message = verified_from_slice(&bytes, &verify_key).unwrap();
+        assert_eq!(message.get("body").and_then(|b| b.as_str()), Some("hello"));
+        assert!(!message.contains_key("signatures"));
+        assert!(!message.contains_key("unsigned"));
+
+        let other_key = VerifyKey::from_signing_key(&SigningKeyPair::generate("other",
+                                                                              "ed25519:1"));
+        match verified_from_slice::<Message, _>(&bytes, &other_key) {
+            Err(VerifyError::Unsigned) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let tampered = String::from_utf8(bytes).unwrap().replace("hello", "goodbye");
+        match verified_from_slice::<Message, _>(tampered.as_bytes(), &verify_key) {
+            Err(VerifyError::Invalid) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn verified_with_options() {
+        let key = SigningKeyPair::generate("domain", "ed25519:1");
+        let verify_key = VerifyKey::from_signing_key(&key);
+
+        let mut value = SignedValue::from_slice(br#"{"body":"hello","score":null}"#).unwrap();
+        key.sign(&mut value);
+        let bytes = value.to_vec().unwrap();
+        let with_nan = String::from_utf8(bytes.clone())
+                           .unwrap()
+                           .replace(r#""score":null"#, r#""score":NaN"#);
+
+        let strict = CanonicalOptions::default();
+        let message: Message = verified_from_slice_with(&bytes, &verify_key, &strict).unwrap();
+        assert_eq!(message.get("body").and_then(|b| b.as_str()), Some("hello"));
+
+        match verified_from_slice_with::<Message, _>(with_nan.as_bytes(), &verify_key, &strict) {
+            Err(VerifyError::Canonical(CanonicalError::NonFiniteNumber { token: "NaN", .. })) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // Quarantined numbers become null, which is what was signed here.
+        let quarantine = CanonicalOptions {
+            non_finite: NonFinitePolicy::Quarantine,
+            ..CanonicalOptions::default()
+        };
+        let message: Message = verified_from_slice_with(with_nan.as_bytes(),
+                                                        &verify_key,
+                                                        &quarantine)
+                                   .unwrap();
+        assert_eq!(message.get("score"), Some(&Value::Null));
+    }
+}
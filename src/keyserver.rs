@@ -0,0 +1,127 @@
+//! A minimal key server: serves an entity's `KeyRing` from
+//! `/_matrix/key/v2/server`, re-signing the response periodically.
+//!
+//! This doesn't depend on any HTTP stack; a handler for e.g. hyper or axum
+//! only needs to pass the request path and current time to `KeyServer::handle`
+//! and return the bytes as `application/json`, or a 404 on `None`.
+
+use serde_json;
+
+use keys::KeyRing;
+
+
+/// The path that server key responses are served from.
+pub const SERVER_KEYS_PATH: &'static str = "/_matrix/key/v2/server";
+
+
+#[derive(Debug, Clone)]
+pub struct KeyServer {
+    ring: KeyRing,
+    /// How long each response is valid for, in milliseconds.
+    validity_period: u64,
+    /// The current response along with when it was generated.
+    cached: Option<(u64, Vec<u8>)>,
+}
+
+impl KeyServer {
+    pub fn new(ring: KeyRing, validity_period: u64) -> KeyServer {
+        KeyServer {
+            ring: ring,
+            validity_period: validity_period,
+            cached: None,
+        }
+    }
+
+    pub fn ring(&self) -> &KeyRing {
+        &self.ring
+    }
+
+    /// Modify the key ring. The next response will be re-signed.
+    pub fn ring_mut(&mut self) -> &mut KeyRing {
+        self.cached = None;
+        &mut self.ring
+    }
+
+    /// The current signed response. It is re-signed once half of its validity
+    /// period has passed, so that fetched copies are always usable for a
+    /// while. `now` is in milliseconds since the epoch.
+    pub fn response(&mut self, now: u64) -> serde_json::Result<&[u8]> {
+        let stale = match self.cached {
+            Some((generated_at, _)) => now >= generated_at + self.validity_period / 2,
+            None => true,
+        };
+
+        if stale {
+            self.cached = None;
+        }
+        let cached = match self.cached.take() {
+            Some(cached) => cached,
+            None => (now, try!(self.ring.server_key_response(now + self.validity_period))),
+        };
+        Ok(&self.cached.get_or_insert(cached).1[..])
+    }
+
+    /// Handle a request for `path`, which is either the server keys path or
+    /// the deprecated form with a trailing key ID. Returns `None` if the path
+    /// isn't one we serve.
+    pub fn handle(&mut self, path: &str, now: u64) -> Option<serde_json::Result<&[u8]>> {
+        if path == SERVER_KEYS_PATH ||
+           (path.starts_with(SERVER_KEYS_PATH) &&
+            path[SERVER_KEYS_PATH.len()..].starts_with('/')) {
+            Some(self.response(now))
+        } else {
+            None
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{KeyRing, NamedPublicKey, NamedSecretKey, SigningKeyPair, VerifyResult,
+               validate_fetched_key_response};
+    use frozen::FrozenStruct;
+    use serde_json::Value;
+    use signed::SimpleSigned;
+
+    const HOUR: u64 = 3600 * 1000;
+
+    #[test]
+    fn serve() {
+        let mut ring = KeyRing::new("example.com");
+        ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:1")).unwrap();
+        let mut server = KeyServer::new(ring, 24 * HOUR);
+
+        let first = server.handle(SERVER_KEYS_PATH, 0).unwrap().unwrap().to_vec();
+        let keys = validate_fetched_key_response(&first, "example.com", HOUR, 24 * HOUR).unwrap();
+        assert_eq!(keys.verify_keys[0].key_id, "ed25519:1");
+
+        // Served from cache until half the validity period has passed.
+        assert_eq!(server.handle("/_matrix/key/v2/server/ed25519:1", HOUR)
+                         .unwrap()
+                         .unwrap(),
+                   &first[..]);
+        assert!(server.handle("/_matrix/key/v2/query", HOUR).is_none());
+        assert!(server.handle("/_matrix/key/v2/serverfoo", HOUR).is_none());
+
+        let resigned = server.response(12 * HOUR).unwrap().to_vec();
+        assert!(resigned != first);
+        let keys = validate_fetched_key_response(&resigned, "example.com", 12 * HOUR, 36 * HOUR)
+                       .unwrap();
+
+        // Keys from the response verify other objects signed by the server.
+        let mut frozen: FrozenStruct<SimpleSigned, Value> = FrozenStruct::from_slice(b"{}")
+                                                                .unwrap();
+        server.ring().signing_keys()[0].sign(&mut frozen);
+        assert_eq!(keys.verify_keys[0].verify(&frozen), VerifyResult::Valid);
+
+        server.ring_mut()
+              .add_signing_key(SigningKeyPair::generate("example.com", "ed25519:2"))
+              .unwrap();
+        let rotated = server.response(12 * HOUR).unwrap().to_vec();
+        let keys = validate_fetched_key_response(&rotated, "example.com", 12 * HOUR, 36 * HOUR)
+                       .unwrap();
+        assert_eq!(keys.verify_keys.len(), 2);
+    }
+}
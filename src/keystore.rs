@@ -0,0 +1,224 @@
+//! Looking up verify keys by entity and key ID, and checking every signature
+//! on an object against them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use sodiumoxide::crypto::sign;
+
+use algorithm::is_supported_key_id;
+use keys::{PublicKey, VerifyKey};
+use signed::{AsCanonical, Signed};
+
+
+pub trait KeyStore {
+    fn get_key(&self, entity: &str, key_id: &str) -> Option<&VerifyKey>;
+
+    /// Check every signature on the object against the known keys.
+    fn verify_object<T>(&self, obj: &T) -> VerificationReport
+        where T: AsCanonical + Signed
+    {
+        let canonical = obj.as_canonical();
+
+        let mut report = VerificationReport::default();
+        for (entity, sigs) in obj.signatures().iter() {
+            let mut entity_report = EntityReport::default();
+            for (key_id, sig) in sigs {
+                if !is_supported_key_id(key_id) {
+                    entity_report.unsupported.push(key_id.clone());
+                    continue;
+                }
+                match self.get_key(entity, key_id) {
+                    Some(key) if sign::verify_detached(sig, &canonical, key.public_key()) => {
+                        entity_report.valid.push(key_id.clone());
+                    }
+                    Some(_) => entity_report.invalid.push(key_id.clone()),
+                    None => entity_report.unknown.push(key_id.clone()),
+                }
+            }
+            report.entities.insert(entity.clone(), entity_report);
+        }
+        for (entity, sigs) in obj.signatures().get_other_signatures() {
+            let entity_report = report.entities
+                                      .entry(entity.clone())
+                                      .or_insert_with(EntityReport::default);
+            entity_report.unsupported.extend(sigs.keys().cloned());
+        }
+        report
+    }
+}
+
+
+/// A `KeyStore` that holds its keys in memory.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryKeyStore {
+    keys: BTreeMap<String, BTreeMap<String, VerifyKey>>,
+    /// Key IDs, by entity, that won't be trusted again.
+    distrusted: BTreeMap<String, BTreeSet<String>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> MemoryKeyStore {
+        MemoryKeyStore::default()
+    }
+
+    /// Add a key, replacing any existing key with the same entity and key ID.
+    /// Keys whose key ID has been distrusted are ignored, see `try_add_key`.
+    pub fn add_key(&mut self, key: VerifyKey) {
+        let _ = self.try_add_key(key);
+    }
+
+    /// Like `add_key`, but returns false if the key was ignored because its
+    /// key ID has been distrusted.
+    #[must_use]
+    pub fn try_add_key(&mut self, key: VerifyKey) -> bool {
+        if self.is_distrusted(&key.entity, &key.key_id) {
+            return false;
+        }
+        self.keys
+            .entry(key.entity.clone())
+            .or_insert_with(BTreeMap::new)
+            .insert(key.key_id.clone(), key);
+        true
+    }
+
+    /// Remove a key and refuse to add it again, e.g. because it was
+    /// compromised.
+    pub fn distrust_key(&mut self, entity: &str, key_id: &str) -> Option<VerifyKey> {
+        self.distrusted
+            .entry(entity.to_string())
+            .or_insert_with(BTreeSet::new)
+            .insert(key_id.to_string());
+        self.remove_key(entity, key_id)
+    }
+
+    pub fn is_distrusted(&self, entity: &str, key_id: &str) -> bool {
+        self.distrusted.get(entity).map_or(false, |key_ids| key_ids.contains(key_id))
+    }
+
+    pub fn remove_key(&mut self, entity: &str, key_id: &str) -> Option<VerifyKey> {
+        self.keys.get_mut(entity).and_then(|keys| keys.remove(key_id))
+    }
+
+    pub fn keys<'a>(&'a self) -> Box<Iterator<Item = &'a VerifyKey> + 'a> {
+        Box::new(self.keys.values().flat_map(|keys| keys.values()))
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn get_key(&self, entity: &str, key_id: &str) -> Option<&VerifyKey> {
+        self.keys.get(entity).and_then(|keys| keys.get(key_id))
+    }
+}
+
+
+/// The outcome of checking the signatures of a single entity.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EntityReport {
+    /// Key IDs with valid signatures.
+    pub valid: Vec<String>,
+    /// Key IDs with signatures that didn't verify against the known key.
+    pub invalid: Vec<String>,
+    /// Key IDs that aren't in the key store.
+    pub unknown: Vec<String>,
+    /// Key IDs whose algorithm isn't supported, i.e. that aren't ed25519.
+    pub unsupported: Vec<String>,
+}
+
+impl EntityReport {
+    /// Whether at least one known key validated.
+    pub fn is_verified(&self) -> bool {
+        !self.valid.is_empty()
+    }
+}
+
+
+/// The outcome of checking every signature on an object, by entity.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerificationReport {
+    pub entities: BTreeMap<String, EntityReport>,
+}
+
+impl VerificationReport {
+    /// Whether at least one of the entity's signatures was valid.
+    pub fn is_verified_by(&self, entity: &str) -> bool {
+        self.entities.get(entity).map_or(false, EntityReport::is_verified)
+    }
+
+    /// The entities with at least one valid signature.
+    pub fn verified_entities(&self) -> Vec<&str> {
+        self.entities
+            .iter()
+            .filter(|&(_, report)| report.is_verified())
+            .map(|(entity, _)| &entity[..])
+            .collect()
+    }
+
+    /// Whether any signature failed to verify against a known key.
+    pub fn has_invalid(&self) -> bool {
+        self.entities.values().any(|report| !report.invalid.is_empty())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use frozen::FrozenStruct;
+    use keys::{NamedSecretKey, SigningKeyPair, VerifyKey};
+    use serde_json::Value;
+    use signed::{SignedMut, SimpleSigned};
+
+    type SimpleFrozen<'a> = FrozenStruct<'a, SimpleSigned, Value>;
+
+    #[test]
+    fn verify_object() {
+        let bytes = br#"{"old_verify_keys":{},"server_name":"jki.re","signatures":{"jki.re":{"ed25519:auto":"X2t7jN0jaJsiZWp57da9GqmQ874QFbukCMSqc5VclaB+2n4i8LPcZDkD6+fzg4tkfpSsiIDogkY4HWv1cnGhAg"}},"tls_fingerprints":[{"sha256":"Big0aXVWZ/m0oEcHddgP4hTriTEvb4Jx6592W1mB5i4"}],"valid_until_ts":1462110302047,"verify_keys":{"ed25519:auto":{"key":"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI"}}}"#;
+        let mut frozen: SimpleFrozen = FrozenStruct::from_slice(bytes).unwrap();
+
+        let other = SigningKeyPair::generate("example.com", "ed25519:1");
+        let unknown = SigningKeyPair::generate("example.com", "ed25519:2");
+        let wrong = SigningKeyPair::generate("example.org", "ed25519:1");
+        other.sign(&mut frozen);
+        unknown.sign(&mut frozen);
+        wrong.sign(&mut frozen);
+
+        let mut store = MemoryKeyStore::new();
+        store.add_key(VerifyKey::from_b64(b"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI",
+                                          "jki.re",
+                                          "ed25519:auto")
+                          .unwrap());
+        store.add_key(VerifyKey::from_signing_key(&other));
+        store.add_key(VerifyKey::from_slice(&SigningKeyPair::generate("x", "y").public.0,
+                                            "example.org",
+                                            "ed25519:1")
+                          .unwrap());
+
+        let report = store.verify_object(&frozen);
+        assert!(report.is_verified_by("jki.re"));
+        assert!(report.is_verified_by("example.com"));
+        assert!(!report.is_verified_by("example.org"));
+        assert!(!report.is_verified_by("example.net"));
+        assert_eq!(report.verified_entities(), vec!["example.com", "jki.re"]);
+        assert!(report.has_invalid());
+
+        assert_eq!(report.entities["example.com"],
+                   EntityReport {
+                       valid: vec!["ed25519:1".to_string()],
+                       invalid: vec![],
+                       unknown: vec!["ed25519:2".to_string()],
+                       unsupported: vec![],
+                   });
+        assert_eq!(report.entities["example.org"].invalid, vec!["ed25519:1".to_string()]);
+
+        frozen.signatures_mut().add_raw_signature("example.net", "curve25519:1", b"sig").unwrap();
+        let report = store.verify_object(&frozen);
+        assert!(!report.is_verified_by("example.net"));
+        assert_eq!(report.entities["example.net"].unsupported,
+                   vec!["curve25519:1".to_string()]);
+
+        assert!(store.remove_key("jki.re", "ed25519:auto").is_some());
+        assert_eq!(store.keys().count(), 2);
+        assert_eq!(store.verify_object(&frozen).entities["jki.re"].unknown,
+                   vec!["ed25519:auto".to_string()]);
+    }
+}
@@ -0,0 +1,49 @@
+//! The public API grouped by what it depends on, so that users who only need
+//! part of it, e.g. embedded or wasm builds, can stick to one layer.
+//!
+//! Each layer after `canonical` is behind a cargo feature of the same name,
+//! which enables the one before it. `canonical` is always compiled, `signing`
+//! adds the dependency on sodiumoxide, and `http` is on by default. As a
+//! module in a layer doesn't exist when its feature is off, building with
+//! `--no-default-features`, optionally with `--features signing`, checks that
+//! no layer uses a later one.
+
+/// Canonical JSON encoding and identifier handling. No signing keys or
+/// network types.
+pub mod canonical {
+    pub use algorithm::{KeyId, NAMESPACE_DELIMITER, key_id_namespace, namespaced_key_id};
+    pub use compare::{constant_time_eq, entity_eq, fingerprint_eq, key_id_eq};
+    pub use ser::{CanonicalError, CanonicalOptions, CanonicalSerializer, NonFinitePolicy,
+                  STRICT_MAX_DEPTH, UNHASHED_KEYS, UNSIGNED_KEYS, canonicalize,
+                  canonicalize_non_finite, canonicalize_strict, canonicalize_with,
+                  encode_canonically, encode_canonically_stripping, replace_non_finite};
+    pub use ser::float::{Es6Float, FixedFloat, FloatFormat, FloatStyle, ShortestFloat};
+}
+
+/// Signed objects: keys, signatures and the serde types that carry them.
+#[cfg(feature = "signing")]
+pub mod signing {
+    pub use error::{Error, Result};
+    pub use frozen::FrozenStruct;
+    pub use hash::{HashResult, Hashed, HashedMut, add_content_hash, compute_content_hash,
+                   verify_content_hash};
+    pub use keys::{NamedPublicKey, NamedSecretKey, SigningKeyPair, VerifyError, VerifyKey,
+                   VerifyResult, verified_from_slice, verified_from_slice_with, verify_batch};
+    pub use ser::signatures::{Base64Signature, SignatureMap};
+    pub use signed::{AsCanonical, GetUnsigned, Signatures, SignaturesMut, Signed, SignedMut,
+                     SimpleSigned};
+    pub use template::Template;
+    pub use value::SignedValue;
+}
+
+/// Fetching, serving and storing other servers' keys.
+#[cfg(feature = "http")]
+pub mod http {
+    pub use archive::{ArchiveError, HistoricalKeyStore, MemoryHistoricalKeyStore, audit_archive};
+    pub use keys::{FetchedServerKeys, KeyResponseError, KeyRing, KeyRingError, ServerKeys,
+                   validate_fetched_key_response};
+    #[cfg(feature = "keyserver")]
+    pub use keyserver::{KeyServer, SERVER_KEYS_PATH};
+    pub use keystore::{KeyStore, MemoryKeyStore, VerificationReport};
+    pub use negative_cache::NegativeCache;
+}
@@ -1,16 +1,63 @@
-extern crate indolentjson;
 extern crate rustc_serialize;
 extern crate serde;
 extern crate serde_json;
+#[cfg(feature = "signing")]
 extern crate sodiumoxide;
 
 #[cfg(test)]
 extern crate itertools;
 
+#[macro_use]
+mod macros;
+
+// Modules are grouped by the cargo feature that enables them, see `layer`.
+pub mod algorithm;
+pub mod compare;
+pub mod error;
+pub mod layer;
+pub mod ser;
+
+#[cfg(feature = "signing")]
+pub mod capabilities;
+#[cfg(feature = "signing")]
+pub mod ceremony;
+#[cfg(feature = "signing")]
+pub mod collector;
+#[cfg(feature = "signing")]
 pub mod frozen;
+#[cfg(feature = "signing")]
+pub mod golden;
+#[cfg(feature = "signing")]
+pub mod hash;
+#[cfg(feature = "signing")]
 pub mod keys;
-pub mod ser;
+#[cfg(feature = "signing")]
+pub mod placeholder;
+#[cfg(feature = "signing")]
+pub mod platform;
+#[cfg(feature = "signing")]
 pub mod signed;
+#[cfg(feature = "signing")]
+pub mod template;
+#[cfg(feature = "signing")]
+pub mod value;
+
+#[cfg(feature = "http")]
+pub mod archive;
+#[cfg(feature = "http")]
+pub mod compromise;
+#[cfg(feature = "keyserver")]
+pub mod keyserver;
+#[cfg(feature = "http")]
+pub mod keystore;
+#[cfg(feature = "http")]
+pub mod negative_cache;
+#[cfg(feature = "http")]
+pub mod quarantine;
+
+#[cfg(feature = "signing")]
+pub use capabilities::capabilities;
+pub use error::Error;
 
 
 use rustc_serialize::base64;
@@ -0,0 +1,139 @@
+//! `signed_struct!`, for implementing the signing traits on user types.
+
+/// Implement `Signed`, `SignedMut`, `AsCanonical` and `GetUnsigned` for a
+/// struct with a `signatures: SignatureMap` field.
+///
+/// The struct must implement `serde::Serialize`; its canonical form is its
+/// serialization without `signatures` and `unsigned`. Pass `unsigned` if the
+/// struct has an `unsigned: Option<U>` field, where `U: Serialize`, to return
+/// it from `get_unsigned`.
+///
+/// # Panics
+///
+/// `as_canonical` panics if the struct fails to serialize, as `AsCanonical`
+/// has no way to return an error. Serializing a struct of plain fields can't
+/// fail, but a custom `Serialize` impl must not return errors, e.g. for maps
+/// with non-string keys.
+///
+/// ```ignore
+/// signed_struct!(Event, unsigned);
+/// ```
+#[macro_export]
+macro_rules! signed_struct {
+    ($name:ident) => {
+        signed_struct!(@signed $name);
+
+        impl $crate::signed::GetUnsigned for $name {
+            fn get_unsigned(&self) -> Option<$crate::signed::JsonValue> {
+                None
+            }
+        }
+    };
+    ($name:ident, unsigned) => {
+        signed_struct!(@signed $name);
+
+        impl $crate::signed::GetUnsigned for $name {
+            fn get_unsigned(&self) -> Option<$crate::signed::JsonValue> {
+                $crate::signed::unsigned_to_value(&self.unsigned)
+            }
+        }
+    };
+    (@signed $name:ident) => {
+        impl $crate::signed::Signed for $name {
+            fn signatures(&self) -> &$crate::ser::signatures::SignatureMap {
+                &self.signatures
+            }
+        }
+
+        impl $crate::signed::SignedMut for $name {
+            fn signatures_mut(&mut self) -> &mut $crate::ser::signatures::SignatureMap {
+                &mut self.signatures
+            }
+        }
+
+        impl $crate::signed::AsCanonical for $name {
+            fn as_canonical(&self) -> ::std::borrow::Cow<[u8]> {
+                $crate::signed::canonical_of(self)
+            }
+        }
+    };
+}
+
+
+#[cfg(all(test, feature = "signing"))]
+mod tests {
+    use keys::{NamedPublicKey, NamedSecretKey, SigningKeyPair, VerifyKey, VerifyResult};
+    use ser::signatures::SignatureMap;
+    use serde;
+    use serde_json::{self, Value};
+    use signed::{AsCanonical, GetUnsigned, Signed};
+
+    struct Event {
+        body: String,
+        signatures: SignatureMap,
+        unsigned: Option<Value>,
+    }
+
+    impl serde::Serialize for Event {
+        fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+            where S: serde::Serializer
+        {
+            let mut state = try!(serializer.serialize_struct("Event", 3));
+            try!(serializer.serialize_struct_elt(&mut state, "body", &self.body));
+            try!(serializer.serialize_struct_elt(&mut state, "signatures", &self.signatures));
+            try!(serializer.serialize_struct_elt(&mut state, "unsigned", &self.unsigned));
+            serializer.serialize_struct_end(state)
+        }
+    }
+
+    signed_struct!(Event, unsigned);
+
+    struct Receipt {
+        event_id: String,
+        signatures: SignatureMap,
+    }
+
+    impl serde::Serialize for Receipt {
+        fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+            where S: serde::Serializer
+        {
+            let mut state = try!(serializer.serialize_struct("Receipt", 2));
+            try!(serializer.serialize_struct_elt(&mut state, "event_id", &self.event_id));
+            try!(serializer.serialize_struct_elt(&mut state, "signatures", &self.signatures));
+            serializer.serialize_struct_end(state)
+        }
+    }
+
+    signed_struct!(Receipt);
+
+    #[test]
+    fn signed_struct() {
+        let mut event = Event {
+            body: "hello".to_string(),
+            signatures: SignatureMap::new(),
+            unsigned: Some(serde_json::from_str(r#"{"age":1}"#).unwrap()),
+        };
+        assert_eq!(&event.as_canonical()[..], &br#"{"body":"hello"}"#[..]);
+        assert_eq!(event.get_unsigned(), event.unsigned.clone());
+
+        let key = SigningKeyPair::generate("domain", "ed25519:1");
+        key.sign(&mut event);
+        assert!(event.signatures().get_signature("domain", "ed25519:1").is_some());
+        assert_eq!(VerifyKey::from_signing_key(&key).verify(&event), VerifyResult::Valid);
+    }
+
+    #[test]
+    fn signed_struct_without_unsigned() {
+        let mut receipt = Receipt {
+            event_id: "$1".to_string(),
+            signatures: SignatureMap::new(),
+        };
+        assert_eq!(&receipt.as_canonical()[..], &br#"{"event_id":"$1"}"#[..]);
+        assert_eq!(receipt.get_unsigned(), None);
+
+        let key = SigningKeyPair::generate("domain", "ed25519:1");
+        key.sign(&mut receipt);
+        assert!(receipt.signatures().get_signature("domain", "ed25519:1").is_some());
+        assert_eq!(VerifyKey::from_signing_key(&key).verify(&receipt), VerifyResult::Valid);
+    }
+}
@@ -0,0 +1,230 @@
+//! Remembering entities whose keys repeatedly failed to fetch or validate, so
+//! that verification pipelines can skip them for a while rather than retrying
+//! on every object. `NegativeCache::validate_key_response` does so for key
+//! responses; failures to fetch one at all are recorded with `record_failure`.
+//!
+//! Timestamps are in milliseconds since the epoch.
+
+use std::collections::BTreeMap;
+
+use keys::{KeyResponseError, ServerKeys, validate_fetched_key_response};
+
+
+/// The recent failures for an entity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FailureEntry {
+    /// The number of failures since the last success.
+    pub failures: u32,
+    pub last_failure_ts: u64,
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NegativeCache {
+    /// The number of failures after which an entity is skipped.
+    threshold: u32,
+    /// How long an entry is kept for after its last failure.
+    ttl: u64,
+    entries: BTreeMap<String, FailureEntry>,
+}
+
+impl NegativeCache {
+    pub fn new(threshold: u32, ttl: u64) -> NegativeCache {
+        NegativeCache {
+            threshold: threshold,
+            ttl: ttl,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Record that fetching or validating the entity's keys failed.
+    pub fn record_failure(&mut self, entity: &str, now: u64) {
+        let ttl = self.ttl;
+        let entry = self.entries.entry(entity.to_string()).or_insert(FailureEntry {
+            failures: 0,
+            last_failure_ts: now,
+        });
+
+        // Failures from before the TTL don't count towards the threshold.
+        if now >= entry.last_failure_ts.saturating_add(ttl) {
+            entry.failures = 0;
+        }
+        entry.failures += 1;
+        entry.last_failure_ts = now;
+    }
+
+    /// Record that the entity's keys were fetched or validated, forgetting
+    /// any failures.
+    pub fn record_success(&mut self, entity: &str) {
+        self.entries.remove(entity);
+    }
+
+    /// If the entity should be skipped, return when it can next be tried.
+    pub fn blocked_until(&self, entity: &str, now: u64) -> Option<u64> {
+        self.entries.get(entity).and_then(|entry| {
+            let until = entry.last_failure_ts.saturating_add(self.ttl);
+            if entry.failures >= self.threshold && now < until {
+                Some(until)
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Whether verification involving the entity should be skipped.
+    pub fn is_blocked(&self, entity: &str, now: u64) -> bool {
+        self.blocked_until(entity, now).is_some()
+    }
+
+    /// Validate a key response fetched from `claimed_server`, as
+    /// `validate_fetched_key_response` does, and record whether it passed.
+    /// While the server is blocked the response isn't looked at, and this
+    /// fails with `KeyResponseError::Blocked`.
+    pub fn validate_key_response(&mut self,
+                                 bytes: &[u8],
+                                 claimed_server: &str,
+                                 now: u64,
+                                 minimum_valid_until: u64)
+                                 -> Result<ServerKeys, KeyResponseError> {
+        if let Some(until) = self.blocked_until(claimed_server, now) {
+            return Err(KeyResponseError::Blocked(until));
+        }
+
+        let res = validate_fetched_key_response(bytes, claimed_server, now, minimum_valid_until);
+        match res {
+            Ok(_) => self.record_success(claimed_server),
+            Err(_) => self.record_failure(claimed_server, now),
+        }
+        res
+    }
+
+    pub fn get(&self, entity: &str) -> Option<&FailureEntry> {
+        self.entries.get(entity)
+    }
+
+    /// All entries, e.g. for persisting the cache.
+    pub fn entries<'a>(&'a self) -> Box<Iterator<Item = (&'a str, &'a FailureEntry)> + 'a> {
+        Box::new(self.entries.iter().map(|(entity, entry)| (&entity[..], entry)))
+    }
+
+    /// Restore an entry, e.g. one previously returned by `entries`.
+    pub fn insert(&mut self, entity: &str, entry: FailureEntry) {
+        self.entries.insert(entity.to_string(), entry);
+    }
+
+    /// Forget the entity's failures. Returns false if there were none.
+    pub fn flush_entity(&mut self, entity: &str) -> bool {
+        self.entries.remove(entity).is_some()
+    }
+
+    /// Forget all failures.
+    pub fn flush(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Drop entries whose TTL has passed.
+    pub fn expire(&mut self, now: u64) {
+        let ttl = self.ttl;
+        let expired: Vec<String> = self.entries
+                                       .iter()
+                                       .filter(|&(_, entry)| {
+                                           now >= entry.last_failure_ts.saturating_add(ttl)
+                                       })
+                                       .map(|(entity, _)| entity.clone())
+                                       .collect();
+        for entity in expired {
+            self.entries.remove(&entity);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{KeyResponseError, KeyRing, SigningKeyPair};
+    use std::u64;
+
+    #[test]
+    fn block_after_threshold() {
+        let mut cache = NegativeCache::new(2, 1000);
+
+        cache.record_failure("example.com", 0);
+        assert!(!cache.is_blocked("example.com", 10));
+
+        cache.record_failure("example.com", 100);
+        assert_eq!(cache.blocked_until("example.com", 200), Some(1100));
+        assert!(!cache.is_blocked("example.com", 1100));
+        assert!(!cache.is_blocked("example.org", 200));
+
+        // The failure count restarts once the TTL has passed.
+        cache.record_failure("example.com", 2000);
+        assert_eq!(cache.get("example.com").unwrap().failures, 1);
+        assert!(!cache.is_blocked("example.com", 2000));
+
+        cache.record_failure("example.com", 2001);
+        assert!(cache.is_blocked("example.com", 2002));
+        cache.record_success("example.com");
+        assert!(!cache.is_blocked("example.com", 2002));
+        assert!(cache.get("example.com").is_none());
+    }
+
+    #[test]
+    fn flush_and_expire() {
+        let mut cache = NegativeCache::new(1, 1000);
+        cache.record_failure("a.example", 0);
+        cache.record_failure("b.example", 500);
+        cache.record_failure("c.example", 500);
+
+        assert!(cache.flush_entity("c.example"));
+        assert!(!cache.flush_entity("c.example"));
+
+        cache.expire(1200);
+        assert_eq!(cache.entries().map(|(entity, _)| entity).collect::<Vec<_>>(),
+                   vec!["b.example"]);
+
+        let mut restored = NegativeCache::new(1, 1000);
+        for (entity, entry) in cache.entries() {
+            restored.insert(entity, *entry);
+        }
+        assert_eq!(restored, cache);
+
+        cache.flush();
+        assert!(cache.entries().next().is_none());
+    }
+
+    #[test]
+    fn long_ttl() {
+        let mut cache = NegativeCache::new(2, u64::MAX);
+        cache.record_failure("example.com", 1);
+        cache.record_failure("example.com", 2);
+        assert_eq!(cache.get("example.com").unwrap().failures, 2);
+        assert_eq!(cache.blocked_until("example.com", 3), Some(u64::MAX));
+
+        cache.expire(u64::MAX - 1);
+        assert!(cache.is_blocked("example.com", u64::MAX - 1));
+    }
+
+    #[test]
+    fn validate_key_response() {
+        let mut ring = KeyRing::new("example.com");
+        ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:1")).unwrap();
+        let response = ring.server_key_response(10000).unwrap();
+
+        let mut cache = NegativeCache::new(2, 1000);
+        assert!(cache.validate_key_response(b"{}", "example.com", 0, 0).is_err());
+        assert!(cache.validate_key_response(&response, "example.org", 100, 0).is_err());
+        assert!(cache.validate_key_response(&response, "example.com", 100, 0).is_ok());
+        assert!(cache.get("example.com").is_none());
+
+        assert!(cache.validate_key_response(b"{}", "example.com", 200, 0).is_err());
+        assert!(cache.validate_key_response(b"{}", "example.com", 300, 0).is_err());
+        match cache.validate_key_response(&response, "example.com", 400, 0) {
+            Err(KeyResponseError::Blocked(1300)) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        assert!(cache.validate_key_response(&response, "example.com", 1300, 0).is_ok());
+        assert!(!cache.is_blocked("example.com", 1300));
+    }
+}
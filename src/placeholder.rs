@@ -0,0 +1,156 @@
+//! Reserving room for a signature that hasn't been made yet, so that the size
+//! of the signed object is known before a slow or remote signer returns.
+
+use rustc_serialize::base64::ToBase64;
+use serde::Serialize;
+use serde::ser::Error;
+use serde_json;
+use sodiumoxide::crypto::sign;
+use sodiumoxide::randombytes::randombytes;
+
+use UNPADDED_BASE64;
+
+
+/// The length of an unpadded base64 encoded ed25519 signature.
+pub const SIGNATURE_B64_LEN: usize = (sign::SIGNATUREBYTES * 4 + 2) / 3;
+
+
+/// A serialized object with a placeholder where a signature will go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    bytes: Vec<u8>,
+    /// Where the placeholder starts in `bytes`.
+    offset: usize,
+}
+
+impl Reservation {
+    /// Serialize the object with a placeholder signature for the given entity
+    /// and key ID, replacing any existing signature by that key.
+    ///
+    /// Fails if there's nowhere to put the signature, i.e. the object isn't a
+    /// JSON object or its `signatures` block isn't a map of maps.
+    pub fn new<T>(obj: &T, entity: &str, key_id: &str) -> serde_json::Result<Reservation>
+        where T: Serialize
+    {
+        // A random placeholder is all but certain not to appear elsewhere in
+        // the object, but check anyway.
+        loop {
+            let placeholder = randombytes(sign::SIGNATUREBYTES).to_base64(UNPADDED_BASE64);
+            let bytes = try!(serialize_with_signature(obj, entity, key_id, &placeholder));
+
+            let quoted = format!("\"{}\"", placeholder);
+            let mut found = find_all(&bytes, quoted.as_bytes());
+            match found.len() {
+                0 => return Err(serde_json::Error::custom("Nowhere to put the signature")),
+                1 => {
+                    return Ok(Reservation {
+                        offset: found.remove(0) + 1,
+                        bytes: bytes,
+                    })
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// The length of the signed object.
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+
+    /// The serialized object, including the placeholder.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Where the signature will go in the serialized object.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Splice in the real signature, giving the signed object.
+    pub fn fill(mut self, sig: &sign::Signature) -> Vec<u8> {
+        let encoded = sig.0.to_base64(UNPADDED_BASE64);
+        self.bytes[self.offset..self.offset + SIGNATURE_B64_LEN]
+            .copy_from_slice(encoded.as_bytes());
+        self.bytes
+    }
+}
+
+fn serialize_with_signature<T>(obj: &T,
+                               entity: &str,
+                               key_id: &str,
+                               sig_b64: &str)
+                               -> serde_json::Result<Vec<u8>>
+    where T: Serialize
+{
+    let mut val = serde_json::to_value(obj);
+    if let Some(obj) = val.as_object_mut() {
+        let signatures = obj.entry(String::from("signatures"))
+                            .or_insert_with(|| serde_json::Value::Object(Default::default()));
+        if let Some(signatures) = signatures.as_object_mut() {
+            let entity_sigs = signatures.entry(entity.to_string())
+                                        .or_insert_with(|| {
+                                            serde_json::Value::Object(Default::default())
+                                        });
+            if let Some(entity_sigs) = entity_sigs.as_object_mut() {
+                entity_sigs.insert(key_id.to_string(),
+                                   serde_json::Value::String(sig_b64.to_string()));
+            }
+        }
+    }
+    serde_json::to_vec(&val)
+}
+
+fn find_all(haystack: &[u8], needle: &[u8]) -> Vec<usize> {
+    haystack.windows(needle.len())
+            .enumerate()
+            .filter(|&(_, window)| window == needle)
+            .map(|(idx, _)| idx)
+            .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{NamedPublicKey, NamedSecretKey, SecretKey, SigningKeyPair, VerifyKey, VerifyResult};
+    use value::SignedValue;
+
+    #[test]
+    fn reserve_and_fill() {
+        assert_eq!(SIGNATURE_B64_LEN, 86);
+
+        let key = SigningKeyPair::generate("domain", "ed25519:1");
+        let other = SigningKeyPair::generate("other", "ed25519:1");
+
+        let mut value = SignedValue::from_slice(br#"{"a":1,"unsigned":{"age":2}}"#).unwrap();
+        other.sign(&mut value);
+
+        let reservation = Reservation::new(&value, "domain", "ed25519:1").unwrap();
+        let len = reservation.len();
+
+        let sig = key.sign_detached(&value);
+        let filled = reservation.fill(&sig);
+        assert_eq!(filled.len(), len);
+
+        key.sign(&mut value);
+        assert_eq!(filled, value.to_vec().unwrap());
+
+        let parsed = SignedValue::from_slice(&filled).unwrap();
+        assert_eq!(VerifyKey::from_signing_key(&key).verify(&parsed), VerifyResult::Valid);
+        assert_eq!(VerifyKey::from_signing_key(&other).verify(&parsed), VerifyResult::Valid);
+    }
+
+    #[test]
+    fn nowhere_to_reserve() {
+        let inputs = [r#"[1,2]"#,
+                      r#""a""#,
+                      r#"{"signatures":1}"#,
+                      r#"{"signatures":{"domain":[]}}"#];
+        for input in &inputs {
+            let value: serde_json::Value = serde_json::from_str(input).unwrap();
+            assert!(Reservation::new(&value, "domain", "ed25519:1").is_err(), "{}", input);
+        }
+    }
+}
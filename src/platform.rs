@@ -0,0 +1,131 @@
+//! Known-answer checks that the crate produces the same bytes on every
+//! platform.
+//!
+//! Nothing in the crate depends on the target's byte order or word size:
+//! integers are formatted as decimal, digests and keys are handled as byte
+//! arrays, and multi-byte numbers derived from them are built up explicitly
+//! most significant byte first. `platform_selftest` lets deployments on less
+//! common targets, e.g. big-endian s390x, confirm that at startup.
+
+use std::error::Error;
+use std::fmt;
+
+use rustc_serialize::base64::{FromBase64, ToBase64};
+use sodiumoxide::crypto::hash::sha256;
+
+use ceremony::verification_code;
+use keys::{NamedPublicKey, NamedSecretKey, SigningKeyPair, VerifyKey, VerifyResult};
+use ser::canonicalize;
+use signed::{AsCanonical, Signed, SignedMut};
+use value::SignedValue;
+use UNPADDED_BASE64;
+
+
+const SEED_B64: &'static str = "YJDBA9Xnr2sVqXD9Vj7XVUnmFZcZrlw8Md7kMW+3XA1";
+const PUBLIC_KEY_B64: &'static str = "XGX0JRS2Af3be3knz2fBiRbApjm2Dh61gXDJA8kcJNI";
+
+const INPUT: &'static [u8] =
+    br#"{"b":[1,-2,18446744073709551615,-9223372036854775808],"a":"\u00e9\u0001"}"#;
+const CANONICAL: &'static [u8] =
+    b"{\"a\":\"\xc3\xa9\\u0001\",\"b\":[1,-2,18446744073709551615,-9223372036854775808]}";
+const HASH_B64: &'static str = "q0CgNm4vPPenZDuPNwKd8lNoc10WRXaKmcYAWJDQAiU";
+const SIGNATURE_B64: &'static str = "1rfUY9imrRgleeUsnAf8v6QczQ/Nc0r38hg/QwrB+ZULMPi6e4j2gL0uA\
+                                     bcs0ohr2g/iqBaOmc3by3yxqNcQBQ";
+const VERIFICATION_CODE: &'static str = "71292 06253 53010 72325 43204 31835";
+
+
+/// The first known-answer check that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum SelftestError {
+    Canonical,
+    Base64,
+    Hash,
+    Sign,
+    Verify,
+    VerificationCode,
+}
+
+impl fmt::Display for SelftestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Platform self-test failed: {}", self.description())
+    }
+}
+
+impl Error for SelftestError {
+    fn description(&self) -> &str {
+        match *self {
+            SelftestError::Canonical => "canonical JSON differs",
+            SelftestError::Base64 => "base64 round trip differs",
+            SelftestError::Hash => "SHA-256 of canonical JSON differs",
+            SelftestError::Sign => "ed25519 signature differs",
+            SelftestError::Verify => "ed25519 verification failed",
+            SelftestError::VerificationCode => "verification code differs",
+        }
+    }
+}
+
+
+/// Check canonicalization, base64, hashing, signing and verification against
+/// fixed known answers, returning the first that doesn't match.
+pub fn platform_selftest() -> Result<(), SelftestError> {
+    match canonicalize(INPUT) {
+        Ok(ref canonical) if &canonical[..] == CANONICAL => {}
+        _ => return Err(SelftestError::Canonical),
+    }
+
+    let all_bytes: Vec<u8> = (0..256).map(|b| b as u8).collect();
+    match all_bytes.to_base64(UNPADDED_BASE64).from_base64() {
+        Ok(ref decoded) if *decoded == all_bytes => {}
+        _ => return Err(SelftestError::Base64),
+    }
+
+    if sha256::hash(CANONICAL).0.to_base64(UNPADDED_BASE64) != HASH_B64 {
+        return Err(SelftestError::Hash);
+    }
+
+    let seed = try!(SEED_B64.from_base64().map_err(|_| SelftestError::Base64));
+    let key = try!(SigningKeyPair::from_seed(&seed, "domain", "ed25519:1")
+                       .map_err(|_| SelftestError::Sign));
+    if key.public_key_b64() != PUBLIC_KEY_B64 {
+        return Err(SelftestError::Sign);
+    }
+
+    let mut value = try!(SignedValue::from_slice(INPUT).map_err(|_| SelftestError::Canonical));
+    if &value.as_canonical()[..] != CANONICAL {
+        return Err(SelftestError::Canonical);
+    }
+    key.sign(&mut value);
+    match value.signatures().get_signature("domain", "ed25519:1") {
+        Some(sig) if sig.0.to_base64(UNPADDED_BASE64) == SIGNATURE_B64 => {}
+        _ => return Err(SelftestError::Sign),
+    }
+
+    let verify_key = VerifyKey::from_signing_key(&key);
+    if verify_key.verify(&value) != VerifyResult::Valid {
+        return Err(SelftestError::Verify);
+    }
+
+    let mut tampered = try!(SignedValue::from_slice(br#"{"a":"e"}"#)
+                                .map_err(|_| SelftestError::Canonical));
+    *tampered.signatures_mut() = value.signatures().clone();
+    if verify_key.verify(&tampered) != VerifyResult::Invalid {
+        return Err(SelftestError::Verify);
+    }
+
+    if verification_code(&key.public) != VERIFICATION_CODE {
+        return Err(SelftestError::VerificationCode);
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn selftest() {
+        assert_eq!(platform_selftest(), Ok(()));
+    }
+}
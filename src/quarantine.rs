@@ -0,0 +1,237 @@
+//! Capturing objects that fail verification, for later analysis of bad
+//! federation traffic.
+//!
+//! `verify_slice` checks an inbound object and, if it fails, hands its raw
+//! bytes, canonical bytes and the reason to an optional `QuarantineSink`.
+//! `BoundedSink` keeps the most recent failures in memory, up to a number of
+//! objects and optionally of bytes.
+
+use std::collections::VecDeque;
+
+use keystore::{KeyStore, VerificationReport};
+use signed::AsCanonical;
+use value::SignedValue;
+
+
+/// Why an object failed verification.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum VerifyFailure {
+    /// The bytes weren't a JSON object with a well formed signatures block.
+    Malformed(String),
+    /// The required entity had no valid signature, or some signature was
+    /// invalid.
+    Unverified(VerificationReport),
+}
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuarantinedObject {
+    pub raw: Vec<u8>,
+    /// The bytes the signatures were checked against, if the object parsed.
+    pub canonical: Option<Vec<u8>>,
+    pub failure: VerifyFailure,
+}
+
+impl QuarantinedObject {
+    /// The size of `raw` and `canonical`, which `BoundedSink` limits.
+    fn size(&self) -> usize {
+        self.raw.len() + self.canonical.as_ref().map_or(0, |canonical| canonical.len())
+    }
+}
+
+
+pub trait QuarantineSink {
+    fn quarantine(&mut self, obj: QuarantinedObject);
+}
+
+impl<F> QuarantineSink for F
+    where F: FnMut(QuarantinedObject)
+{
+    fn quarantine(&mut self, obj: QuarantinedObject) {
+        (*self)(obj)
+    }
+}
+
+
+/// Keeps the most recent `capacity` quarantined objects, dropping the oldest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BoundedSink {
+    capacity: usize,
+    /// The most bytes of `raw` and `canonical` to hold in total, if limited.
+    max_bytes: Option<usize>,
+    objects: VecDeque<QuarantinedObject>,
+    bytes: usize,
+    dropped: u64,
+}
+
+impl BoundedSink {
+    pub fn new(capacity: usize) -> BoundedSink {
+        BoundedSink {
+            capacity: capacity,
+            max_bytes: None,
+            objects: VecDeque::new(),
+            bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Like `new`, but also keep the total size of the objects' `raw` and
+    /// `canonical` bytes within `max_bytes`, dropping the oldest objects to
+    /// make room. An object larger than that on its own is dropped.
+    pub fn with_max_bytes(capacity: usize, max_bytes: usize) -> BoundedSink {
+        BoundedSink { max_bytes: Some(max_bytes), ..BoundedSink::new(capacity) }
+    }
+
+    pub fn objects(&self) -> &VecDeque<QuarantinedObject> {
+        &self.objects
+    }
+
+    /// The total size of the objects' `raw` and `canonical` bytes.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// The number of objects dropped to stay within capacity.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    /// Remove and return everything captured so far.
+    pub fn drain(&mut self) -> Vec<QuarantinedObject> {
+        self.bytes = 0;
+        self.objects.drain(..).collect()
+    }
+
+    fn exceeds_max_bytes(&self, bytes: usize) -> bool {
+        self.max_bytes.map_or(false, |max_bytes| bytes > max_bytes)
+    }
+}
+
+impl QuarantineSink for BoundedSink {
+    fn quarantine(&mut self, obj: QuarantinedObject) {
+        let size = obj.size();
+        if self.capacity == 0 || self.exceeds_max_bytes(size) {
+            self.dropped += 1;
+            return;
+        }
+        while self.objects.len() == self.capacity || self.exceeds_max_bytes(self.bytes + size) {
+            if let Some(old) = self.objects.pop_front() {
+                self.bytes -= old.size();
+                self.dropped += 1;
+            }
+        }
+        self.bytes += size;
+        self.objects.push_back(obj);
+    }
+}
+
+
+/// Parse the object and check that `entity` signed it and that no signature
+/// is invalid. On failure the object is passed to `sink`, if given.
+pub fn verify_slice<S>(bytes: &[u8],
+                       store: &S,
+                       entity: &str,
+                       sink: Option<&mut QuarantineSink>)
+                       -> Result<SignedValue, VerifyFailure>
+    where S: KeyStore
+{
+    let (canonical, failure) = match SignedValue::from_slice(bytes) {
+        Err(err) => (None, VerifyFailure::Malformed(err.to_string())),
+        Ok(value) => {
+            let report = store.verify_object(&value);
+            if report.is_verified_by(entity) && !report.has_invalid() {
+                return Ok(value);
+            }
+            (Some(value.as_canonical().into_owned()), VerifyFailure::Unverified(report))
+        }
+    };
+
+    if let Some(sink) = sink {
+        sink.quarantine(QuarantinedObject {
+            raw: bytes.to_vec(),
+            canonical: canonical,
+            failure: failure.clone(),
+        });
+    }
+    Err(failure)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{NamedSecretKey, SigningKeyPair, VerifyKey};
+    use keystore::MemoryKeyStore;
+    use std::iter;
+
+    #[test]
+    fn quarantine() {
+        let key = SigningKeyPair::generate("example.com", "ed25519:1");
+        let mut store = MemoryKeyStore::new();
+        store.add_key(VerifyKey::from_signing_key(&key));
+
+        let mut value = SignedValue::from_slice(br#"{"a":1}"#).unwrap();
+        key.sign(&mut value);
+        let signed = value.to_vec().unwrap();
+
+        let mut sink = BoundedSink::new(2);
+        assert!(verify_slice(&signed, &store, "example.com", Some(&mut sink)).is_ok());
+        assert!(sink.objects().is_empty());
+
+        let tampered = String::from_utf8(signed.clone()).unwrap().replace(r#""a":1"#, r#""a":2"#);
+        match verify_slice(tampered.as_bytes(), &store, "example.com", Some(&mut sink)) {
+            Err(VerifyFailure::Unverified(ref report)) => assert!(report.has_invalid()),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(sink.objects()[0].raw, tampered.as_bytes());
+        assert_eq!(sink.objects()[0].canonical, Some(br#"{"a":2}"#.to_vec()));
+
+        // Unsigned by the required entity, and not JSON at all.
+        assert!(verify_slice(&signed, &store, "example.org", Some(&mut sink)).is_err());
+        assert!(verify_slice(b"nope", &store, "example.com", Some(&mut sink)).is_err());
+        assert_eq!(sink.objects().len(), 2);
+        assert_eq!(sink.dropped(), 1);
+        assert_eq!(sink.objects()[1].canonical, None);
+        match sink.objects()[1].failure {
+            VerifyFailure::Malformed(_) => {}
+            ref other => panic!("{:?}", other),
+        }
+
+        // Without a sink, or with a closure.
+        assert!(verify_slice(b"nope", &store, "example.com", None).is_err());
+        let mut count = 0;
+        {
+            let mut counter = |_: QuarantinedObject| count += 1;
+            assert!(verify_slice(b"nope", &store, "example.com", Some(&mut counter)).is_err());
+        }
+        assert_eq!(count, 1);
+        assert_eq!(sink.drain().len(), 2);
+        assert!(sink.objects().is_empty());
+        assert_eq!(sink.bytes(), 0);
+    }
+
+    #[test]
+    fn max_bytes() {
+        let store = MemoryKeyStore::new();
+        let mut sink = BoundedSink::with_max_bytes(10, 20);
+
+        // Each is 7 bytes raw and 7 canonical.
+        assert!(verify_slice(br#"{"a":1}"#, &store, "example.com", Some(&mut sink)).is_err());
+        assert_eq!(sink.bytes(), 14);
+        assert!(verify_slice(br#"{"a":2}"#, &store, "example.com", Some(&mut sink)).is_err());
+        assert_eq!(sink.objects().len(), 1);
+        assert_eq!(sink.objects()[0].raw, br#"{"a":2}"#.to_vec());
+        assert_eq!(sink.bytes(), 14);
+        assert_eq!(sink.dropped(), 1);
+
+        // One that wouldn't fit even on its own is dropped, keeping the rest.
+        let large = format!(r#"{{"a":"{}"}}"#, iter::repeat("x").take(20).collect::<String>());
+        assert!(verify_slice(large.as_bytes(), &store, "example.com", Some(&mut sink)).is_err());
+        assert_eq!(sink.objects().len(), 1);
+        assert_eq!(sink.dropped(), 2);
+
+        assert!(verify_slice(b"nope", &store, "example.com", Some(&mut sink)).is_err());
+        assert_eq!(sink.objects().len(), 2);
+        assert_eq!(sink.bytes(), 18);
+    }
+}
@@ -0,0 +1,969 @@
+//! A `serde::Serializer` that writes canonical JSON directly, i.e. compact
+//! output with object keys sorted by code point.
+//!
+//! Arrays and scalars are written straight to the output. Object entries have
+//! to be buffered so that they can be sorted, but are only copied once per
+//! level of nesting.
+
+use std::cell::Cell;
+
+use serde;
+use serde::ser::Error;
+use serde_json;
+
+use super::float::{FloatFormat, ShortestFloat};
+
+
+const HEX_DIGITS: &'static [u8] = b"0123456789abcdef";
+
+
+/// A cap on how many bytes serializing a value may hold at once, counting both
+/// the output and the buffered entries of objects still being written.
+///
+/// The serializers for nested values share it, so that once serializing
+/// fails the caller can tell whether this was why.
+#[derive(Debug)]
+pub struct OutputLimit {
+    max_bytes: usize,
+    exceeded: Cell<bool>,
+}
+
+impl OutputLimit {
+    pub fn new(max_bytes: usize) -> OutputLimit {
+        OutputLimit {
+            max_bytes: max_bytes,
+            exceeded: Cell::new(false),
+        }
+    }
+
+    pub fn max_bytes(&self) -> usize {
+        self.max_bytes
+    }
+
+    pub fn exceeded(&self) -> bool {
+        self.exceeded.get()
+    }
+}
+
+
+/// Serializes values as canonical JSON into a byte buffer.
+pub struct CanonicalSerializer<'a> {
+    out: &'a mut Vec<u8>,
+    /// Keys to leave out of the top level object, e.g. `signatures`.
+    stripped_keys: &'a [&'a str],
+    float_format: &'a FloatFormat,
+    limit: Option<&'a OutputLimit>,
+    /// Bytes held by enclosing serializers in buffers other than `out`.
+    held: usize,
+}
+
+impl<'a> CanonicalSerializer<'a> {
+    pub fn new(out: &'a mut Vec<u8>) -> CanonicalSerializer<'a> {
+        CanonicalSerializer {
+            out: out,
+            stripped_keys: &[],
+            float_format: &ShortestFloat,
+            limit: None,
+            held: 0,
+        }
+    }
+
+    /// Create a serializer that leaves the given keys out of the top level
+    /// object. Nested objects are written in full.
+    pub fn with_stripped_keys(out: &'a mut Vec<u8>,
+                              stripped_keys: &'a [&'a str])
+                              -> CanonicalSerializer<'a> {
+        CanonicalSerializer {
+            out: out,
+            stripped_keys: stripped_keys,
+            float_format: &ShortestFloat,
+            limit: None,
+            held: 0,
+        }
+    }
+
+    /// Use the given formatting for floats, at every level of nesting.
+    pub fn float_format(mut self, float_format: &'a FloatFormat) -> CanonicalSerializer<'a> {
+        self.float_format = float_format;
+        self
+    }
+
+    /// Fail, rather than keep allocating, once the limit is exceeded.
+    pub fn output_limit(mut self, limit: &'a OutputLimit) -> CanonicalSerializer<'a> {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Whether `pending` more bytes, buffered by this serializer outside of
+    /// `out` or about to be written, are within the limit.
+    fn fits(&self, pending: usize) -> bool {
+        self.limit.map_or(true, |limit| {
+            self.held.saturating_add(self.out.len()).saturating_add(pending) <= limit.max_bytes
+        })
+    }
+
+    /// Check the limit, given `pending` bytes buffered by this serializer
+    /// outside of `out`.
+    fn check_limit(&self, pending: usize) -> Result<(), serde_json::Error> {
+        if let Some(limit) = self.limit {
+            if !self.fits(pending) {
+                limit.exceeded.set(true);
+                return Err(serde_json::Error::custom(format!("Canonical output exceeds {} bytes",
+                                                             limit.max_bytes)));
+            }
+        }
+        Ok(())
+    }
+
+    fn start_elt(&mut self, first: &mut bool) {
+        if *first {
+            *first = false;
+        } else {
+            self.out.push(b',');
+        }
+    }
+
+    fn map_key(&mut self, state: &mut MapState, key: String) {
+        state.skip = self.stripped_keys.iter().any(|&k| k == key);
+        state.key = Some(key);
+    }
+
+    fn map_value<T>(&mut self, state: &mut MapState, value: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        let key = try!(state.key
+                            .take()
+                            .ok_or_else(|| serde_json::Error::custom("Map value without a key")));
+        if state.skip {
+            return Ok(());
+        }
+
+        let mut buf = Vec::new();
+        let held = self.held + self.out.len() + state.buffered;
+        try!(value.serialize(&mut nested(&mut buf, self.float_format, self.limit, held)));
+        state.buffered += key.len() + buf.len();
+        state.entries.push((key, buf));
+        self.check_limit(state.buffered)
+    }
+
+    fn map_end(&mut self, state: MapState) -> Result<(), serde_json::Error> {
+        let MapState { mut entries, variant, .. } = state;
+
+        if let Some(variant) = variant {
+            self.out.push(b'{');
+            write_str(self.out, variant);
+            self.out.push(b':');
+        }
+
+        // The sort is stable, so for duplicate keys the last one wins.
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        self.out.push(b'{');
+        let mut first = true;
+        for (idx, &(ref key, ref value)) in entries.iter().enumerate() {
+            if entries.get(idx + 1).map_or(false, |next| next.0 == *key) {
+                continue;
+            }
+            self.start_elt(&mut first);
+            write_str(self.out, key);
+            self.out.push(b':');
+            self.out.extend_from_slice(value);
+        }
+        self.out.push(b'}');
+
+        if variant.is_some() {
+            self.out.push(b'}');
+        }
+        self.check_limit(0)
+    }
+}
+
+
+/// Buffered entries of an object being serialized.
+#[doc(hidden)]
+pub struct MapState {
+    entries: Vec<(String, Vec<u8>)>,
+    /// The total size of the keys and values in `entries`.
+    buffered: usize,
+    key: Option<String>,
+    /// Whether the current entry is being left out.
+    skip: bool,
+    /// The variant name, if this is a struct variant.
+    variant: Option<&'static str>,
+}
+
+impl MapState {
+    fn new(len: Option<usize>, variant: Option<&'static str>) -> MapState {
+        MapState {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            buffered: 0,
+            key: None,
+            skip: false,
+            variant: variant,
+        }
+    }
+}
+
+
+/// The length of `value` once quoted and escaped by `write_str`.
+fn escaped_len(value: &str) -> usize {
+    value.bytes().fold(2, |len, byte| {
+        len +
+        match byte {
+            b'"' | b'\\' | b'\x08' | b'\x0c' | b'\n' | b'\r' | b'\t' => 2,
+            byte if byte < 0x20 => 6,
+            _ => 1,
+        }
+    })
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) {
+    out.push(b'"');
+
+    let bytes = value.as_bytes();
+    let mut start = 0;
+    for (idx, &byte) in bytes.iter().enumerate() {
+        let escape: &[u8] = match byte {
+            b'"' => b"\\\"",
+            b'\\' => b"\\\\",
+            b'\x08' => b"\\b",
+            b'\x0c' => b"\\f",
+            b'\n' => b"\\n",
+            b'\r' => b"\\r",
+            b'\t' => b"\\t",
+            byte if byte < 0x20 => b"",
+            _ => continue,
+        };
+
+        out.extend_from_slice(&bytes[start..idx]);
+        if escape.is_empty() {
+            out.extend_from_slice(b"\\u00");
+            out.push(HEX_DIGITS[(byte >> 4) as usize]);
+            out.push(HEX_DIGITS[(byte & 0xf) as usize]);
+        } else {
+            out.extend_from_slice(escape);
+        }
+        start = idx + 1;
+    }
+    out.extend_from_slice(&bytes[start..]);
+
+    out.push(b'"');
+}
+
+fn write_u64(out: &mut Vec<u8>, mut value: u64) {
+    let mut buf = [0u8; 20];
+    let mut pos = buf.len();
+    loop {
+        pos -= 1;
+        buf[pos] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(&buf[pos..]);
+}
+
+fn write_i64(out: &mut Vec<u8>, value: i64) {
+    if value < 0 {
+        out.push(b'-');
+        write_u64(out, (!(value as u64)).wrapping_add(1));
+    } else {
+        write_u64(out, value as u64);
+    }
+}
+
+/// A serializer for a value inside an array or object, which keeps the float
+/// formatting and output limit but not the stripped keys.
+fn nested<'b>(out: &'b mut Vec<u8>,
+              float_format: &'b FloatFormat,
+              limit: Option<&'b OutputLimit>,
+              held: usize)
+              -> CanonicalSerializer<'b> {
+    CanonicalSerializer {
+        out: out,
+        stripped_keys: &[],
+        float_format: float_format,
+        limit: limit,
+        held: held,
+    }
+}
+
+
+impl<'a> serde::Serializer for CanonicalSerializer<'a> {
+    type Error = serde_json::Error;
+    type SeqState = bool;
+    type TupleState = bool;
+    type TupleStructState = bool;
+    type TupleVariantState = bool;
+    type MapState = MapState;
+    type StructState = MapState;
+    type StructVariantState = MapState;
+
+    fn serialize_bool(&mut self, value: bool) -> Result<(), serde_json::Error> {
+        self.out.extend_from_slice(if value { &b"true"[..] } else { &b"false"[..] });
+        Ok(())
+    }
+
+    fn serialize_isize(&mut self, value: isize) -> Result<(), serde_json::Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i8(&mut self, value: i8) -> Result<(), serde_json::Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i16(&mut self, value: i16) -> Result<(), serde_json::Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i32(&mut self, value: i32) -> Result<(), serde_json::Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i64(&mut self, value: i64) -> Result<(), serde_json::Error> {
+        write_i64(self.out, value);
+        Ok(())
+    }
+
+    fn serialize_usize(&mut self, value: usize) -> Result<(), serde_json::Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u8(&mut self, value: u8) -> Result<(), serde_json::Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u16(&mut self, value: u16) -> Result<(), serde_json::Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u32(&mut self, value: u32) -> Result<(), serde_json::Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u64(&mut self, value: u64) -> Result<(), serde_json::Error> {
+        write_u64(self.out, value);
+        Ok(())
+    }
+
+    fn serialize_f32(&mut self, value: f32) -> Result<(), serde_json::Error> {
+        self.serialize_f64(value as f64)
+    }
+
+    fn serialize_f64(&mut self, value: f64) -> Result<(), serde_json::Error> {
+        self.float_format.write_f64(self.out, value)
+    }
+
+    fn serialize_char(&mut self, value: char) -> Result<(), serde_json::Error> {
+        let mut buf = String::with_capacity(4);
+        buf.push(value);
+        self.serialize_str(&buf)
+    }
+
+    fn serialize_str(&mut self, value: &str) -> Result<(), serde_json::Error> {
+        // Escaping at most sextuples the length, so only count exactly when
+        // that doesn't fit.
+        if !self.fits(value.len().saturating_mul(6).saturating_add(2)) {
+            try!(self.check_limit(escaped_len(value)));
+        }
+        write_str(self.out, value);
+        Ok(())
+    }
+
+    fn serialize_bytes(&mut self, value: &[u8]) -> Result<(), serde_json::Error> {
+        let mut state = try!(self.serialize_seq(Some(value.len())));
+        for byte in value {
+            try!(self.serialize_seq_elt(&mut state, byte));
+        }
+        self.serialize_seq_end(state)
+    }
+
+    fn serialize_unit(&mut self) -> Result<(), serde_json::Error> {
+        self.out.extend_from_slice(b"null");
+        Ok(())
+    }
+
+    fn serialize_unit_struct(&mut self, _name: &'static str) -> Result<(), serde_json::Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_unit_variant(&mut self,
+                              _name: &'static str,
+                              _variant_index: usize,
+                              variant: &'static str)
+                              -> Result<(), serde_json::Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T>(&mut self,
+                                   _name: &'static str,
+                                   value: T)
+                                   -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(&mut self,
+                                    _name: &'static str,
+                                    _variant_index: usize,
+                                    variant: &'static str,
+                                    value: T)
+                                    -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.out.push(b'{');
+        write_str(self.out, variant);
+        self.out.push(b':');
+        try!(value.serialize(&mut nested(self.out, self.float_format, self.limit, self.held)));
+        self.out.push(b'}');
+        Ok(())
+    }
+
+    fn serialize_none(&mut self) -> Result<(), serde_json::Error> {
+        self.serialize_unit()
+    }
+
+    fn serialize_some<T>(&mut self, value: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(&mut self, _len: Option<usize>) -> Result<bool, serde_json::Error> {
+        self.out.push(b'[');
+        Ok(true)
+    }
+
+    fn serialize_seq_elt<T>(&mut self, first: &mut bool, value: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.start_elt(first);
+        try!(value.serialize(&mut nested(self.out, self.float_format, self.limit, self.held)));
+        self.check_limit(0)
+    }
+
+    fn serialize_seq_end(&mut self, _first: bool) -> Result<(), serde_json::Error> {
+        self.out.push(b']');
+        Ok(())
+    }
+
+    fn serialize_seq_fixed_size(&mut self, size: usize) -> Result<bool, serde_json::Error> {
+        self.serialize_seq(Some(size))
+    }
+
+    fn serialize_tuple(&mut self, len: usize) -> Result<bool, serde_json::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_elt<T>(&mut self, first: &mut bool, value: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.serialize_seq_elt(first, value)
+    }
+
+    fn serialize_tuple_end(&mut self, first: bool) -> Result<(), serde_json::Error> {
+        self.serialize_seq_end(first)
+    }
+
+    fn serialize_tuple_struct(&mut self,
+                              _name: &'static str,
+                              len: usize)
+                              -> Result<bool, serde_json::Error> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct_elt<T>(&mut self,
+                                     first: &mut bool,
+                                     value: T)
+                                     -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.serialize_seq_elt(first, value)
+    }
+
+    fn serialize_tuple_struct_end(&mut self, first: bool) -> Result<(), serde_json::Error> {
+        self.serialize_seq_end(first)
+    }
+
+    fn serialize_tuple_variant(&mut self,
+                               _name: &'static str,
+                               _variant_index: usize,
+                               variant: &'static str,
+                               len: usize)
+                               -> Result<bool, serde_json::Error> {
+        self.out.push(b'{');
+        write_str(self.out, variant);
+        self.out.push(b':');
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant_elt<T>(&mut self,
+                                      first: &mut bool,
+                                      value: T)
+                                      -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.serialize_seq_elt(first, value)
+    }
+
+    fn serialize_tuple_variant_end(&mut self, first: bool) -> Result<(), serde_json::Error> {
+        try!(self.serialize_seq_end(first));
+        self.out.push(b'}');
+        Ok(())
+    }
+
+    fn serialize_map(&mut self, len: Option<usize>) -> Result<MapState, serde_json::Error> {
+        Ok(MapState::new(len, None))
+    }
+
+    fn serialize_map_key<T>(&mut self, state: &mut MapState, key: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        let mut key_serializer = KeySerializer { key: None };
+        try!(key.serialize(&mut key_serializer));
+        let key = try!(key_serializer.key
+                                     .ok_or_else(|| serde_json::Error::custom("Key must be a string")));
+        self.map_key(state, key);
+        Ok(())
+    }
+
+    fn serialize_map_value<T>(&mut self,
+                              state: &mut MapState,
+                              value: T)
+                              -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.map_value(state, value)
+    }
+
+    fn serialize_map_end(&mut self, state: MapState) -> Result<(), serde_json::Error> {
+        self.map_end(state)
+    }
+
+    fn serialize_struct(&mut self,
+                        _name: &'static str,
+                        len: usize)
+                        -> Result<MapState, serde_json::Error> {
+        Ok(MapState::new(Some(len), None))
+    }
+
+    fn serialize_struct_elt<V>(&mut self,
+                               state: &mut MapState,
+                               key: &'static str,
+                               value: V)
+                               -> Result<(), serde_json::Error>
+        where V: serde::Serialize
+    {
+        self.map_key(state, key.to_string());
+        self.map_value(state, value)
+    }
+
+    fn serialize_struct_end(&mut self, state: MapState) -> Result<(), serde_json::Error> {
+        self.map_end(state)
+    }
+
+    fn serialize_struct_variant(&mut self,
+                                _name: &'static str,
+                                _variant_index: usize,
+                                variant: &'static str,
+                                len: usize)
+                                -> Result<MapState, serde_json::Error> {
+        Ok(MapState::new(Some(len), Some(variant)))
+    }
+
+    fn serialize_struct_variant_elt<V>(&mut self,
+                                       state: &mut MapState,
+                                       key: &'static str,
+                                       value: V)
+                                       -> Result<(), serde_json::Error>
+        where V: serde::Serialize
+    {
+        self.map_key(state, key.to_string());
+        self.map_value(state, value)
+    }
+
+    fn serialize_struct_variant_end(&mut self, state: MapState) -> Result<(), serde_json::Error> {
+        self.map_end(state)
+    }
+}
+
+
+/// Captures an object key, which must be a string (or a number, which is
+/// written as a string).
+struct KeySerializer {
+    key: Option<String>,
+}
+
+impl KeySerializer {
+    fn invalid<T>(&self) -> Result<T, serde_json::Error> {
+        Err(serde_json::Error::custom("Key must be a string"))
+    }
+}
+
+impl serde::Serializer for KeySerializer {
+    type Error = serde_json::Error;
+    type SeqState = ();
+    type TupleState = ();
+    type TupleStructState = ();
+    type TupleVariantState = ();
+    type MapState = ();
+    type StructState = ();
+    type StructVariantState = ();
+
+    fn serialize_bool(&mut self, _value: bool) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_isize(&mut self, value: isize) -> Result<(), serde_json::Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i8(&mut self, value: i8) -> Result<(), serde_json::Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i16(&mut self, value: i16) -> Result<(), serde_json::Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i32(&mut self, value: i32) -> Result<(), serde_json::Error> {
+        self.serialize_i64(value as i64)
+    }
+
+    fn serialize_i64(&mut self, value: i64) -> Result<(), serde_json::Error> {
+        self.key = Some(value.to_string());
+        Ok(())
+    }
+
+    fn serialize_usize(&mut self, value: usize) -> Result<(), serde_json::Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u8(&mut self, value: u8) -> Result<(), serde_json::Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u16(&mut self, value: u16) -> Result<(), serde_json::Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u32(&mut self, value: u32) -> Result<(), serde_json::Error> {
+        self.serialize_u64(value as u64)
+    }
+
+    fn serialize_u64(&mut self, value: u64) -> Result<(), serde_json::Error> {
+        self.key = Some(value.to_string());
+        Ok(())
+    }
+
+    fn serialize_f32(&mut self, _value: f32) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_f64(&mut self, _value: f64) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_char(&mut self, value: char) -> Result<(), serde_json::Error> {
+        self.key = Some(value.to_string());
+        Ok(())
+    }
+
+    fn serialize_str(&mut self, value: &str) -> Result<(), serde_json::Error> {
+        self.key = Some(value.to_string());
+        Ok(())
+    }
+
+    fn serialize_bytes(&mut self, _value: &[u8]) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_unit(&mut self) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_unit_struct(&mut self, _name: &'static str) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_unit_variant(&mut self,
+                              _name: &'static str,
+                              _variant_index: usize,
+                              variant: &'static str)
+                              -> Result<(), serde_json::Error> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T>(&mut self,
+                                   _name: &'static str,
+                                   value: T)
+                                   -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(&mut self,
+                                    _name: &'static str,
+                                    _variant_index: usize,
+                                    _variant: &'static str,
+                                    _value: T)
+                                    -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.invalid()
+    }
+
+    fn serialize_none(&mut self) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_some<T>(&mut self, value: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_seq(&mut self, _len: Option<usize>) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_seq_elt<T>(&mut self, _state: &mut (), _value: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.invalid()
+    }
+
+    fn serialize_seq_end(&mut self, _state: ()) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_seq_fixed_size(&mut self, _size: usize) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_tuple(&mut self, _len: usize) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_tuple_elt<T>(&mut self, _state: &mut (), _value: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.invalid()
+    }
+
+    fn serialize_tuple_end(&mut self, _state: ()) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_tuple_struct(&mut self,
+                              _name: &'static str,
+                              _len: usize)
+                              -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_tuple_struct_elt<T>(&mut self,
+                                     _state: &mut (),
+                                     _value: T)
+                                     -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.invalid()
+    }
+
+    fn serialize_tuple_struct_end(&mut self, _state: ()) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_tuple_variant(&mut self,
+                               _name: &'static str,
+                               _variant_index: usize,
+                               _variant: &'static str,
+                               _len: usize)
+                               -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_tuple_variant_elt<T>(&mut self,
+                                      _state: &mut (),
+                                      _value: T)
+                                      -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.invalid()
+    }
+
+    fn serialize_tuple_variant_end(&mut self, _state: ()) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_map(&mut self, _len: Option<usize>) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_map_key<T>(&mut self, _state: &mut (), _key: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.invalid()
+    }
+
+    fn serialize_map_value<T>(&mut self, _state: &mut (), _value: T) -> Result<(), serde_json::Error>
+        where T: serde::Serialize
+    {
+        self.invalid()
+    }
+
+    fn serialize_map_end(&mut self, _state: ()) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_struct(&mut self,
+                        _name: &'static str,
+                        _len: usize)
+                        -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_struct_elt<V>(&mut self,
+                               _state: &mut (),
+                               _key: &'static str,
+                               _value: V)
+                               -> Result<(), serde_json::Error>
+        where V: serde::Serialize
+    {
+        self.invalid()
+    }
+
+    fn serialize_struct_end(&mut self, _state: ()) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_struct_variant(&mut self,
+                                _name: &'static str,
+                                _variant_index: usize,
+                                _variant: &'static str,
+                                _len: usize)
+                                -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+
+    fn serialize_struct_variant_elt<V>(&mut self,
+                                       _state: &mut (),
+                                       _key: &'static str,
+                                       _value: V)
+                                       -> Result<(), serde_json::Error>
+        where V: serde::Serialize
+    {
+        self.invalid()
+    }
+
+    fn serialize_struct_variant_end(&mut self, _state: ()) -> Result<(), serde_json::Error> {
+        self.invalid()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::iter;
+    use serde::Serialize;
+    use serde_json;
+
+    fn encode<S: Serialize>(value: &S) -> Vec<u8> {
+        let mut out = Vec::new();
+        value.serialize(&mut CanonicalSerializer::new(&mut out)).unwrap();
+        out
+    }
+
+    #[test]
+    fn sorted_compact() {
+        let value: serde_json::Value = serde_json::from_str(r#"{
+            "b": [1, -2, true, null, "x"],
+            "a": {"z": {}, "y": []},
+            "": 18446744073709551615,
+            "c": -9223372036854775808
+        }"#).unwrap();
+
+        assert_eq!(&encode(&value)[..],
+                   &br#"{"":18446744073709551615,"a":{"y":[],"z":{}},"b":[1,-2,true,null,"x"],"c":-9223372036854775808}"#[..]);
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(&encode(&"a\"b\\c\n\u{1}\u{1f}é日本")[..],
+                   &b"\"a\\\"b\\\\c\\n\\u0001\\u001f\xc3\xa9\xe6\x97\xa5\xe6\x9c\xac\""[..]);
+    }
+
+    #[test]
+    fn map_keys() {
+        let mut map = BTreeMap::new();
+        map.insert(2u64, "b");
+        map.insert(10u64, "a");
+
+        // Numeric keys are sorted as strings.
+        assert_eq!(&encode(&map)[..], &br#"{"10":"a","2":"b"}"#[..]);
+    }
+
+    #[test]
+    fn stripped_keys() {
+        let value: serde_json::Value =
+            serde_json::from_str(r#"{"a":{"signatures":1},"signatures":{},"unsigned":{}}"#)
+                .unwrap();
+
+        let mut out = Vec::new();
+        value.serialize(&mut CanonicalSerializer::with_stripped_keys(&mut out,
+                                                                     &["signatures", "unsigned"]))
+             .unwrap();
+        assert_eq!(&out[..], &br#"{"a":{"signatures":1}}"#[..]);
+    }
+
+    #[test]
+    fn float_format() {
+        use ser::float::{Es6Float, FixedFloat};
+
+        let value: serde_json::Value =
+            serde_json::from_str(r#"{"a":[1e21,{"b":0.5}],"c":-0.0,"d":2.0}"#).unwrap();
+
+        assert_eq!(&encode(&vec![0.5, 2.0])[..], &b"[0.5,2.0]"[..]);
+
+        let mut out = Vec::new();
+        value.serialize(&mut CanonicalSerializer::new(&mut out).float_format(&Es6Float)).unwrap();
+        assert_eq!(&out[..], &br#"{"a":[1e+21,{"b":0.5}],"c":0,"d":2}"#[..]);
+
+        let mut out = Vec::new();
+        value.serialize(&mut CanonicalSerializer::new(&mut out)
+                                 .float_format(&FixedFloat { precision: 1 }))
+             .unwrap();
+        assert_eq!(&out[..], &br#"{"a":[1000000000000000000000.0,{"b":0.5}],"c":-0.0,"d":2.0}"#[..]);
+
+        let mut out = Vec::new();
+        assert!(::std::f64::NAN.serialize(&mut CanonicalSerializer::new(&mut out)
+                                                   .float_format(&Es6Float))
+                               .is_err());
+    }
+
+    #[test]
+    fn string_limit() {
+        // Each control character is escaped to six bytes.
+        let value: String = iter::repeat('\x01').take(10).collect();
+        let escaped_len = 2 + 6 * 10;
+
+        let limit = OutputLimit::new(escaped_len);
+        let mut out = Vec::new();
+        value.serialize(&mut CanonicalSerializer::new(&mut out).output_limit(&limit)).unwrap();
+        assert_eq!(out.len(), escaped_len);
+
+        let limit = OutputLimit::new(escaped_len - 1);
+        let mut out = Vec::new();
+        assert!(value.serialize(&mut CanonicalSerializer::new(&mut out).output_limit(&limit))
+                     .is_err());
+        assert!(limit.exceeded());
+        // Refused before any of it was written.
+        assert!(out.is_empty());
+    }
+}
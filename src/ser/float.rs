@@ -0,0 +1,200 @@
+//! How the canonical encoder writes floats.
+//!
+//! Canonical JSON proper doesn't allow floats, but profiles that do, such as
+//! JCS (RFC 8785), have to agree on their formatting exactly. This is the main
+//! source of disagreement between implementations in different languages, so
+//! the strategy is pluggable.
+
+use serde::ser::Error;
+use serde_json;
+
+
+pub trait FloatFormat {
+    fn write_f64(&self, out: &mut Vec<u8>, value: f64) -> Result<(), serde_json::Error>;
+}
+
+
+/// serde_json's shortest round-trip formatting, e.g. `1.0`, `0.00001` and
+/// `1e21`, with `null` for non-finite values. This is the default, as it's
+/// what objects signed by earlier versions of this crate were encoded with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ShortestFloat;
+
+impl FloatFormat for ShortestFloat {
+    fn write_f64(&self, out: &mut Vec<u8>, value: f64) -> Result<(), serde_json::Error> {
+        out.extend_from_slice(&try!(serde_json::to_vec(&value)));
+        Ok(())
+    }
+}
+
+
+/// ECMAScript's `Number.prototype.toString` formatting, as required by JCS,
+/// e.g. `1`, `0.000001` and `1e+21`. Non-finite values are an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Es6Float;
+
+impl FloatFormat for Es6Float {
+    fn write_f64(&self, out: &mut Vec<u8>, value: f64) -> Result<(), serde_json::Error> {
+        if !value.is_finite() {
+            return Err(serde_json::Error::custom("Non-finite float"));
+        }
+        if value == 0.0 {
+            // Including negative zero.
+            out.push(b'0');
+            return Ok(());
+        }
+        if value < 0.0 {
+            out.push(b'-');
+        }
+
+        // The shortest round-trip digits and the exponent of the first one.
+        let sci = format!("{:e}", value.abs());
+        let (mantissa, exp) = match sci.find('e') {
+            Some(idx) => (&sci[..idx], &sci[idx + 1..]),
+            None => (&sci[..], "0"),
+        };
+        let digits: String = mantissa.chars().filter(|&c| c != '.').collect();
+        let exp: i32 = try!(exp.parse().map_err(|_| serde_json::Error::custom("Bad float")));
+
+        // In the terms of the ECMAScript spec, the value is digits * 10^(n-k).
+        let k = digits.len() as i32;
+        let n = exp + 1;
+
+        if k <= n && n <= 21 {
+            out.extend_from_slice(digits.as_bytes());
+            for _ in 0..(n - k) {
+                out.push(b'0');
+            }
+        } else if 0 < n && n <= 21 {
+            out.extend_from_slice(digits[..n as usize].as_bytes());
+            out.push(b'.');
+            out.extend_from_slice(digits[n as usize..].as_bytes());
+        } else if -6 < n && n <= 0 {
+            out.extend_from_slice(b"0.");
+            for _ in 0..-n {
+                out.push(b'0');
+            }
+            out.extend_from_slice(digits.as_bytes());
+        } else {
+            out.extend_from_slice(digits[..1].as_bytes());
+            if k > 1 {
+                out.push(b'.');
+                out.extend_from_slice(digits[1..].as_bytes());
+            }
+            out.extend_from_slice(format!("e{}{}", if n > 0 { "+" } else { "-" }, (n - 1).abs())
+                                      .as_bytes());
+        }
+        Ok(())
+    }
+}
+
+
+/// A fixed number of decimal places, e.g. `1.50` with a precision of 2, with
+/// `null` for non-finite values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct FixedFloat {
+    pub precision: usize,
+}
+
+impl FloatFormat for FixedFloat {
+    fn write_f64(&self, out: &mut Vec<u8>, value: f64) -> Result<(), serde_json::Error> {
+        if value.is_finite() {
+            out.extend_from_slice(format!("{:.*}", self.precision, value).as_bytes());
+        } else {
+            out.extend_from_slice(b"null");
+        }
+        Ok(())
+    }
+}
+
+
+/// One of the formats above, for choosing one in `CanonicalOptions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FloatStyle {
+    Shortest,
+    Es6,
+    Fixed(FixedFloat),
+}
+
+impl Default for FloatStyle {
+    fn default() -> FloatStyle {
+        FloatStyle::Shortest
+    }
+}
+
+impl FloatFormat for FloatStyle {
+    fn write_f64(&self, out: &mut Vec<u8>, value: f64) -> Result<(), serde_json::Error> {
+        match *self {
+            FloatStyle::Shortest => ShortestFloat.write_f64(out, value),
+            FloatStyle::Es6 => Es6Float.write_f64(out, value),
+            FloatStyle::Fixed(ref fixed) => fixed.write_f64(out, value),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    fn format<F: FloatFormat>(format: &F, value: f64) -> String {
+        let mut out = Vec::new();
+        format.write_f64(&mut out, value).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn shortest() {
+        assert_eq!(format(&ShortestFloat, 1.5), "1.5");
+        assert_eq!(format(&ShortestFloat, 0.1), "0.1");
+        assert_eq!(format(&ShortestFloat, ::std::f64::NAN), "null");
+
+        // These must stay byte-identical to serde_json's, or signatures made
+        // by earlier versions stop verifying.
+        let cases: &[(f64, &str)] = &[(1.0, "1.0"),
+                                      (1e16, "10000000000000000.0"),
+                                      (1e17, "100000000000000000.0"),
+                                      (1e-5, "0.00001"),
+                                      (1e-6, "0.000001")];
+        for &(value, expected) in cases {
+            assert_eq!(format(&ShortestFloat, value), expected);
+            assert_eq!(format(&ShortestFloat, value),
+                       String::from_utf8(serde_json::to_vec(&value).unwrap()).unwrap());
+        }
+    }
+
+    #[test]
+    fn es6() {
+        // Examples from RFC 8785 and the ECMAScript spec.
+        let cases: &[(f64, &str)] = &[(0.0, "0"),
+                                      (-0.0, "0"),
+                                      (1.0, "1"),
+                                      (-1.5, "-1.5"),
+                                      (4.5, "4.5"),
+                                      (0.002, "0.002"),
+                                      (0.000001, "0.000001"),
+                                      (1e-7, "1e-7"),
+                                      (123.456, "123.456"),
+                                      (333333333.3333333, "333333333.3333333"),
+                                      (9007199254740992.0, "9007199254740992"),
+                                      (1e20, "100000000000000000000"),
+                                      (295147905179352830000.0, "295147905179352830000"),
+                                      (1e21, "1e+21"),
+                                      (1.7976931348623157e308, "1.7976931348623157e+308"),
+                                      (5e-324, "5e-324")];
+        for &(value, expected) in cases {
+            assert_eq!(format(&Es6Float, value), expected);
+        }
+
+        let mut out = Vec::new();
+        assert!(Es6Float.write_f64(&mut out, ::std::f64::INFINITY).is_err());
+    }
+
+    #[test]
+    fn fixed() {
+        assert_eq!(format(&FixedFloat { precision: 2 }, 1.5), "1.50");
+        assert_eq!(format(&FixedFloat { precision: 0 }, 2.5), "2");
+        assert_eq!(format(&FixedFloat { precision: 3 }, -0.0005), "-0.001");
+    }
+}
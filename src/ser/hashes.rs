@@ -0,0 +1,85 @@
+use std::ops::{Deref, DerefMut};
+
+use rustc_serialize::base64::{FromBase64, ToBase64};
+
+use serde;
+use serde::de::Error;
+
+use sodiumoxide::crypto::hash::sha256;
+
+use UNPADDED_BASE64;
+
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Base64Hash(sha256::Digest);
+
+impl Deref for Base64Hash {
+    type Target = sha256::Digest;
+
+    fn deref(&self) -> &sha256::Digest {
+        &self.0
+    }
+}
+
+impl DerefMut for Base64Hash {
+    fn deref_mut(&mut self) -> &mut sha256::Digest {
+        &mut self.0
+    }
+}
+
+impl From<sha256::Digest> for Base64Hash {
+    fn from(hash: sha256::Digest) -> Base64Hash {
+        Base64Hash(hash)
+    }
+}
+
+impl From<Base64Hash> for sha256::Digest {
+    fn from(hash: Base64Hash) -> sha256::Digest {
+        hash.0
+    }
+}
+
+impl serde::Serialize for Base64Hash {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        serializer.serialize_str(&self.0[..].to_base64(UNPADDED_BASE64))
+    }
+}
+
+impl serde::Deserialize for Base64Hash {
+    fn deserialize<D>(deserializer: &mut D) -> Result<Base64Hash, D::Error>
+        where D: serde::Deserializer
+    {
+        let de_string: String = try!(<String as serde::Deserialize>::deserialize(deserializer));
+
+        let hash = try!(de_string.from_base64()
+                                 .ok()
+                                 .and_then(|slice| sha256::Digest::from_slice(&slice))
+                                 .ok_or_else(|| D::Error::invalid_value("Invalid hash")));
+
+        Ok(Base64Hash(hash))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sodiumoxide::crypto::hash::sha256;
+
+    use serde_json;
+
+    #[test]
+    fn round_trip() {
+        let hash = Base64Hash(sha256::hash(br#"{"a":1,"b":"x"}"#));
+        let serialized = serde_json::to_string(&hash).unwrap();
+
+        assert_eq!(serialized, r#""7PnpjsBkHiMRP/POi9x40N3SSYhlF/1Kf2jMg9TmVmc""#);
+
+        let de_hash: Base64Hash = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(de_hash, hash);
+
+        assert!(serde_json::from_str::<Base64Hash>(r#""7PnpjsBk""#).is_err());
+    }
+}
@@ -1,37 +1,317 @@
+pub mod canonical;
+pub mod float;
+#[cfg(feature = "signing")]
+pub mod hashes;
+#[cfg(feature = "signing")]
 pub mod signatures;
 
-use indolentjson::compact::compact as compact_json;
+mod scan;
+mod strict;
+
+use std::borrow::Cow;
+use std::error::Error;
+use std::fmt;
 
 use serde::Serialize;
 use serde_json;
 
+pub use self::canonical::{CanonicalSerializer, OutputLimit};
+use self::float::{FloatFormat, FloatStyle, ShortestFloat};
+
+
+/// Top level keys that aren't covered by signatures.
+pub const UNSIGNED_KEYS: &'static [&'static str] = &["signatures", "unsigned"];
+
+/// Top level keys that aren't covered by content hashes.
+pub const UNHASHED_KEYS: &'static [&'static str] = &["hashes", "signatures", "unsigned"];
+
+
 pub fn canonicalize(bytes: &[u8]) -> serde_json::Result<Vec<u8>> {
     let val: serde_json::Value = try!(serde_json::from_slice(bytes));
     encode_canonically(&val)
 }
 
 pub fn encode_canonically<S: Serialize>(st: &S) -> serde_json::Result<Vec<u8>> {
-    let mut val: serde_json::Value = serde_json::to_value(st);
+    encode_canonically_stripping(st, UNSIGNED_KEYS)
+}
+
+/// Encode canonically, leaving out the given top level keys rather than
+/// `signatures` and `unsigned`.
+pub fn encode_canonically_stripping<S: Serialize>(st: &S,
+                                                  stripped_keys: &[&str])
+                                                  -> serde_json::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    try!(st.serialize(&mut CanonicalSerializer::with_stripped_keys(&mut out, stripped_keys)));
+    Ok(out)
+}
+
+/// Like `encode_canonically_stripping`, but fail with
+/// `CanonicalError::OutputTooLarge` rather than use more than `max_bytes` of
+/// memory for the output.
+pub fn encode_canonically_limited<S: Serialize>(st: &S,
+                                                stripped_keys: &[&str],
+                                                max_bytes: usize)
+                                                -> Result<Vec<u8>, CanonicalError> {
+    encode_with(st, stripped_keys, &ShortestFloat, Some(max_bytes))
+}
+
+/// Encode canonically with the given float formatting and optional limit.
+fn encode_with<S: Serialize>(st: &S,
+                             stripped_keys: &[&str],
+                             float_format: &FloatFormat,
+                             max_bytes: Option<usize>)
+                             -> Result<Vec<u8>, CanonicalError> {
+    let limit = max_bytes.map(OutputLimit::new);
+    let mut out = Vec::new();
+    let res = {
+        let mut ser = CanonicalSerializer::with_stripped_keys(&mut out, stripped_keys)
+                          .float_format(float_format);
+        if let Some(ref limit) = limit {
+            ser = ser.output_limit(limit);
+        }
+        st.serialize(&mut ser)
+    };
+    match (res, limit) {
+        (Ok(()), _) => Ok(out),
+        (Err(_), Some(ref limit)) if limit.exceeded() => {
+            Err(CanonicalError::OutputTooLarge { max_bytes: limit.max_bytes() })
+        }
+        (Err(err), _) => Err(CanonicalError::Json(err)),
+    }
+}
+
+
+#[derive(Debug)]
+pub enum CanonicalError {
+    Json(serde_json::Error),
+    /// A `NaN`, `Infinity` or `-Infinity` token was found at the given byte
+    /// offset.
+    NonFiniteNumber {
+        offset: usize,
+        token: &'static str,
+    },
+    /// A float, which canonical JSON doesn't allow, at the given JSON pointer.
+    Float { path: String },
+    /// An integer outside of ±(2^53 - 1) at the given JSON pointer.
+    IntegerOutOfRange { path: String },
+    /// An object key, given as a JSON pointer, that appears more than once.
+    DuplicateKey { path: String },
+    /// An array or object nested more than `max_depth` levels deep opens at
+    /// the given byte offset.
+    TooDeep { offset: usize, max_depth: usize },
+    /// The output would have been larger than `max_bytes`.
+    OutputTooLarge { max_bytes: usize },
+}
+
+impl From<serde_json::Error> for CanonicalError {
+    fn from(err: serde_json::Error) -> CanonicalError {
+        CanonicalError::Json(err)
+    }
+}
+
+impl fmt::Display for CanonicalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CanonicalError::Json(ref err) => write!(f, "Invalid JSON: {}", err),
+            CanonicalError::NonFiniteNumber { offset, token } => {
+                write!(f, "Non-finite number {} at offset {}", token, offset)
+            }
+            CanonicalError::Float { ref path } => {
+                write!(f, "Float at {} not allowed in canonical JSON", path)
+            }
+            CanonicalError::IntegerOutOfRange { ref path } => {
+                write!(f, "Integer at {} is outside of the range ±(2^53 - 1)", path)
+            }
+            CanonicalError::DuplicateKey { ref path } => write!(f, "Duplicate key {}", path),
+            CanonicalError::TooDeep { offset, max_depth } => {
+                write!(f, "Nested more than {} levels deep at offset {}", max_depth, offset)
+            }
+            CanonicalError::OutputTooLarge { max_bytes } => {
+                write!(f, "Canonical output larger than {} bytes", max_bytes)
+            }
+        }
+    }
+}
+
+impl Error for CanonicalError {
+    fn description(&self) -> &str {
+        match *self {
+            CanonicalError::Json(_) => "invalid JSON",
+            CanonicalError::NonFiniteNumber { .. } => "non-finite number",
+            CanonicalError::Float { .. } => "float in canonical JSON",
+            CanonicalError::IntegerOutOfRange { .. } => "integer out of range",
+            CanonicalError::DuplicateKey { .. } => "duplicate key",
+            CanonicalError::TooDeep { .. } => "nested too deeply",
+            CanonicalError::OutputTooLarge { .. } => "output too large",
+        }
+    }
+}
+
+
+/// How to treat the `NaN`, `Infinity` and `-Infinity` tokens that some
+/// producers emit, despite them not being valid JSON.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum NonFinitePolicy {
+    /// Fail with `CanonicalError::NonFiniteNumber`.
+    Reject,
+    /// Replace them with `null`. The result can't match what the producer
+    /// signed, so this is only useful for diagnostics.
+    Quarantine,
+}
+
+/// Handle any non-finite numbers in the input according to `policy`, giving
+/// the input unchanged if there are none.
+pub fn replace_non_finite<'a>(bytes: &'a [u8],
+                              policy: NonFinitePolicy)
+                              -> Result<Cow<'a, [u8]>, CanonicalError> {
+    let tokens = scan::find_non_finite(bytes);
+    if tokens.is_empty() {
+        return Ok(Cow::Borrowed(bytes));
+    }
+
+    match policy {
+        NonFinitePolicy::Reject => {
+            let (offset, token) = tokens[0];
+            Err(CanonicalError::NonFiniteNumber {
+                offset: offset,
+                token: token,
+            })
+        }
+        NonFinitePolicy::Quarantine => Ok(Cow::Owned(scan::replace_with_null(bytes, &tokens))),
+    }
+}
+
+/// Canonicalize input that may contain non-finite numbers, handling them
+/// according to `policy` rather than failing with a generic parse error.
+pub fn canonicalize_non_finite(bytes: &[u8],
+                               policy: NonFinitePolicy)
+                               -> Result<Vec<u8>, CanonicalError> {
+    canonicalize_with(bytes,
+                      &CanonicalOptions { non_finite: policy, ..CanonicalOptions::default() })
+}
+
+
+/// The nesting depth allowed by `CanonicalOptions::strict`.
+pub const STRICT_MAX_DEPTH: usize = 100;
+
+/// Which input to refuse when canonicalizing, beyond invalid JSON.
+///
+/// The default accepts everything `canonicalize` does; `strict` refuses
+/// anything that the Matrix canonical JSON rules forbid, and so that other
+/// implementations may canonicalize differently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CanonicalOptions {
+    pub reject_floats: bool,
+    /// Reject integers outside of ±(2^53 - 1).
+    pub reject_large_integers: bool,
+    /// Reject objects with the same key more than once, rather than keeping
+    /// the last value.
+    pub reject_duplicate_keys: bool,
+    /// The maximum nesting depth of arrays and objects, where the top level
+    /// value is at depth 1. This is checked before parsing.
+    pub max_depth: Option<usize>,
+    pub non_finite: NonFinitePolicy,
+    /// The most memory, in bytes, to use for the canonical output.
+    pub max_output: Option<usize>,
+    /// How to write floats that aren't refused.
+    pub float_format: FloatStyle,
+}
 
-    if let Some(obj) = val.as_object_mut() {
-        obj.remove("signatures");
-        obj.remove("unsigned");
+impl CanonicalOptions {
+    pub fn strict() -> CanonicalOptions {
+        CanonicalOptions {
+            reject_floats: true,
+            reject_large_integers: true,
+            reject_duplicate_keys: true,
+            max_depth: Some(STRICT_MAX_DEPTH),
+            non_finite: NonFinitePolicy::Reject,
+            max_output: None,
+            float_format: FloatStyle::Shortest,
+        }
     }
 
-    // TODO: Assumes BTreeMap is serialized in key order
-    let uncompact = try!(serde_json::to_vec(&val));
+    /// Close to JCS (RFC 8785): floats are written as ECMAScript does, and
+    /// input that I-JSON forbids is refused.
+    ///
+    /// Keys are still sorted by their UTF-8 bytes rather than UTF-16 code
+    /// units, which only differs for objects with keys that have characters
+    /// from U+E000 to U+FFFF and ones above U+FFFF at the same position.
+    pub fn jcs() -> CanonicalOptions {
+        CanonicalOptions {
+            reject_floats: false,
+            reject_large_integers: true,
+            reject_duplicate_keys: true,
+            max_depth: None,
+            non_finite: NonFinitePolicy::Reject,
+            max_output: None,
+            float_format: FloatStyle::Es6,
+        }
+    }
+
+    fn rejects(&self, kind: strict::IssueKind) -> bool {
+        match kind {
+            strict::IssueKind::Float => self.reject_floats,
+            strict::IssueKind::IntegerOutOfRange => self.reject_large_integers,
+            strict::IssueKind::DuplicateKey => self.reject_duplicate_keys,
+        }
+    }
+}
+
+impl Default for CanonicalOptions {
+    fn default() -> CanonicalOptions {
+        CanonicalOptions {
+            reject_floats: false,
+            reject_large_integers: false,
+            reject_duplicate_keys: false,
+            max_depth: None,
+            non_finite: NonFinitePolicy::Reject,
+            max_output: None,
+            float_format: FloatStyle::Shortest,
+        }
+    }
+}
+
+/// Canonicalize, refusing the input the options say to.
+pub fn canonicalize_with(bytes: &[u8],
+                         options: &CanonicalOptions)
+                         -> Result<Vec<u8>, CanonicalError> {
+    if let Some(max_depth) = options.max_depth {
+        if let Some(offset) = scan::find_excess_depth(bytes, max_depth) {
+            return Err(CanonicalError::TooDeep {
+                offset: offset,
+                max_depth: max_depth,
+            });
+        }
+    }
+
+    let bytes = try!(replace_non_finite(bytes, options.non_finite));
+    let parsed: strict::StrictValue = try!(serde_json::from_slice(&bytes));
+    if let Some(issue) = parsed.issues.iter().find(|issue| options.rejects(issue.kind)) {
+        let path = issue.pointer();
+        return Err(match issue.kind {
+            strict::IssueKind::Float => CanonicalError::Float { path: path },
+            strict::IssueKind::IntegerOutOfRange => {
+                CanonicalError::IntegerOutOfRange { path: path }
+            }
+            strict::IssueKind::DuplicateKey => CanonicalError::DuplicateKey { path: path },
+        });
+    }
 
-    let mut new_vec = Vec::with_capacity(uncompact.len());
-    compact_json(&uncompact, &mut new_vec).expect("Invalid JSON");
+    encode_with(&parsed.value, UNSIGNED_KEYS, &options.float_format, options.max_output)
+}
 
-    Ok(new_vec)
+/// Canonicalize, refusing anything the canonical JSON rules forbid.
+pub fn canonicalize_strict(bytes: &[u8]) -> Result<Vec<u8>, CanonicalError> {
+    canonicalize_with(bytes, &CanonicalOptions::strict())
 }
 
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use ser::float::{FixedFloat, FloatStyle};
     use serde_json;
+    use std::iter;
 
     #[test]
     fn canonical() {
@@ -49,4 +329,119 @@ mod tests {
 
         assert_eq!(&canonical[..], &br#"{"old_verify_keys":{},"server_name":"jki.re","tls_fingerprints":[{"sha256":"Big0aXVWZ/m0oEcHddgP4hTriTEvb4Jx6592W1mB5i4"}],"valid_until_ts":1462110302047,"verify_keys":{"ed25519:auto":{"key":"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI"}}}"#[..]);
     }
+
+    #[test]
+    fn float_compatibility() {
+        // Floats must be written exactly as serde_json writes them, as that's
+        // what objects signed by earlier versions were encoded with.
+        let bytes = br#"[1e16,1e17,1e-5,1e-6,1.5,1]"#;
+        let expected = br#"[10000000000000000.0,100000000000000000.0,0.00001,0.000001,1.5,1]"#;
+        assert_eq!(&canonicalize(bytes).unwrap()[..], &expected[..]);
+
+        let value: serde_json::Value = serde_json::from_slice(bytes).unwrap();
+        assert_eq!(encode_canonically(&value).unwrap(), serde_json::to_vec(&value).unwrap());
+    }
+
+    #[test]
+    fn non_finite() {
+        let bytes = br#"{"b":"NaN","a":[1,-Infinity]}"#;
+
+        match canonicalize_non_finite(bytes, NonFinitePolicy::Reject) {
+            Err(CanonicalError::NonFiniteNumber { offset: 18, token: "-Infinity" }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let canonical = canonicalize_non_finite(bytes, NonFinitePolicy::Quarantine).unwrap();
+        assert_eq!(&canonical[..], &br#"{"a":[1,null],"b":"NaN"}"#[..]);
+
+        let canonical = canonicalize_non_finite(br#"{"b":1,"a":2}"#, NonFinitePolicy::Reject)
+                            .unwrap();
+        assert_eq!(&canonical[..], &br#"{"a":2,"b":1}"#[..]);
+    }
+
+    #[test]
+    fn float_format() {
+        assert_eq!(&canonicalize_with(br#"{"b":0.5,"a":1.0}"#, &CanonicalOptions::default())
+                        .unwrap()[..],
+                   &br#"{"a":1.0,"b":0.5}"#[..]);
+
+        let bytes = br#"{"c":1.0,"b":1e21,"a":0.000001}"#;
+        assert_eq!(&canonicalize_with(bytes, &CanonicalOptions::jcs()).unwrap()[..],
+                   &br#"{"a":0.000001,"b":1e+21,"c":1}"#[..]);
+
+        let options = CanonicalOptions {
+            float_format: FloatStyle::Fixed(FixedFloat { precision: 2 }),
+            ..CanonicalOptions::default()
+        };
+        assert_eq!(&canonicalize_with(br#"{"a":[0.5,1]}"#, &options).unwrap()[..],
+                   &br#"{"a":[0.50,1]}"#[..]);
+
+        match canonicalize_with(br#"{"a":1,"a":2}"#, &CanonicalOptions::jcs()) {
+            Err(CanonicalError::DuplicateKey { ref path }) if path == "/a" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn strict() {
+        assert_eq!(&canonicalize_strict(br#"{"b":9007199254740991,"a":[-1]}"#).unwrap()[..],
+                   &br#"{"a":[-1],"b":9007199254740991}"#[..]);
+
+        match canonicalize_strict(br#"{"a":[1,1.5]}"#) {
+            Err(CanonicalError::Float { ref path }) if path == "/a/1" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match canonicalize_strict(br#"{"a":{"b":-9007199254740992}}"#) {
+            Err(CanonicalError::IntegerOutOfRange { ref path }) if path == "/a/b" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match canonicalize_strict(br#"{"a":1,"a":2}"#) {
+            Err(CanonicalError::DuplicateKey { ref path }) if path == "/a" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let deep: String = iter::repeat("[")
+                               .take(STRICT_MAX_DEPTH + 1)
+                               .chain(iter::repeat("]").take(STRICT_MAX_DEPTH + 1))
+                               .collect();
+        match canonicalize_strict(deep.as_bytes()) {
+            Err(CanonicalError::TooDeep { offset, max_depth }) => {
+                assert_eq!(offset, STRICT_MAX_DEPTH);
+                assert_eq!(max_depth, STRICT_MAX_DEPTH);
+            }
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        // The default options accept all of it, last duplicate winning.
+        let lenient = canonicalize_with(br#"{"a":1.5,"a":18446744073709551615}"#,
+                                        &CanonicalOptions::default())
+                          .unwrap();
+        assert_eq!(&lenient[..], &br#"{"a":18446744073709551615}"#[..]);
+    }
+
+    #[test]
+    fn output_limit() {
+        let long: String = iter::repeat("x").take(100).collect();
+        let bytes = format!(r#"{{"b":{{"c":["{}"]}},"a":[1,2,3],"signatures":{{}}}}"#, long);
+        let expected = format!(r#"{{"a":[1,2,3],"b":{{"c":["{}"]}}}}"#, long);
+        let value: serde_json::Value = serde_json::from_str(&bytes).unwrap();
+
+        let canonical = encode_canonically_limited(&value, UNSIGNED_KEYS, expected.len()).unwrap();
+        assert_eq!(canonical, expected.as_bytes());
+
+        for &max_bytes in &[expected.len() - 1, 50, 0] {
+            match encode_canonically_limited(&value, UNSIGNED_KEYS, max_bytes) {
+                Err(CanonicalError::OutputTooLarge { max_bytes: m }) if m == max_bytes => {}
+                res => panic!("Unexpected result for {}: {:?}", max_bytes, res),
+            }
+        }
+
+        let options = CanonicalOptions { max_output: Some(50), ..CanonicalOptions::default() };
+        match canonicalize_with(bytes.as_bytes(), &options) {
+            Err(CanonicalError::OutputTooLarge { max_bytes: 50 }) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        let options = CanonicalOptions { max_output: Some(1000), ..CanonicalOptions::default() };
+        assert_eq!(canonicalize_with(bytes.as_bytes(), &options).unwrap(), expected.as_bytes());
+    }
 }
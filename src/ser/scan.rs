@@ -0,0 +1,107 @@
+//! Scanning raw JSON bytes for things that are lost once parsed.
+
+/// Tokens that some producers emit for non-finite floats.
+const NON_FINITE_TOKENS: &'static [&'static str] = &["-Infinity", "Infinity", "NaN"];
+
+
+/// Find the `NaN`, `Infinity` and `-Infinity` tokens outside of strings,
+/// returning the byte offset of each along with the token.
+pub fn find_non_finite(bytes: &[u8]) -> Vec<(usize, &'static str)> {
+    let mut found = Vec::new();
+
+    let mut in_string = false;
+    let mut escaped = false;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        let byte = bytes[idx];
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if byte == b'\\' {
+                escaped = true;
+            } else if byte == b'"' {
+                in_string = false;
+            }
+        } else if byte == b'"' {
+            in_string = true;
+        } else if let Some(token) = NON_FINITE_TOKENS.iter()
+                                                     .find(|t| bytes[idx..].starts_with(t.as_bytes())) {
+            found.push((idx, *token));
+            idx += token.len();
+            continue;
+        }
+        idx += 1;
+    }
+
+    found
+}
+
+/// Find the first array or object that opens deeper than `max_depth` levels
+/// of nesting, returning its byte offset. The top level value is at depth 1.
+pub fn find_excess_depth(bytes: &[u8], max_depth: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (idx, &byte) in bytes.iter().enumerate() {
+        if in_string {
+            if escaped {
+                escaped = false;
+            } else if byte == b'\\' {
+                escaped = true;
+            } else if byte == b'"' {
+                in_string = false;
+            }
+        } else {
+            match byte {
+                b'"' => in_string = true,
+                b'[' | b'{' => {
+                    depth += 1;
+                    if depth > max_depth {
+                        return Some(idx);
+                    }
+                }
+                b']' | b'}' => depth -= if depth > 0 { 1 } else { 0 },
+                _ => {}
+            }
+        }
+    }
+    None
+}
+
+/// Replace the given tokens, as returned by `find_non_finite`, with `null`.
+pub fn replace_with_null(bytes: &[u8], tokens: &[(usize, &'static str)]) -> Vec<u8> {
+    let mut replaced = Vec::with_capacity(bytes.len());
+    let mut start = 0;
+    for &(offset, token) in tokens {
+        replaced.extend_from_slice(&bytes[start..offset]);
+        replaced.extend_from_slice(b"null");
+        start = offset + token.len();
+    }
+    replaced.extend_from_slice(&bytes[start..]);
+    replaced
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite() {
+        let bytes = br#"{"a":NaN,"b":[Infinity,-Infinity],"NaN":"Infinity \" NaN"}"#;
+        let found = find_non_finite(bytes);
+        assert_eq!(found, vec![(5, "NaN"), (14, "Infinity"), (23, "-Infinity")]);
+
+        assert_eq!(&replace_with_null(bytes, &found)[..],
+                   &br#"{"a":null,"b":[null,null],"NaN":"Infinity \" NaN"}"#[..]);
+    }
+
+    #[test]
+    fn depth() {
+        let bytes = br#"{"a":[{"b":"[[[{{{"}],"c":[[1]]}"#;
+        assert_eq!(find_excess_depth(bytes, 3), None);
+        assert_eq!(find_excess_depth(bytes, 2), Some(6));
+        assert_eq!(find_excess_depth(b"[]", 0), Some(0));
+        assert_eq!(find_excess_depth(b"1", 0), None);
+    }
+}
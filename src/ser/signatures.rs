@@ -1,13 +1,15 @@
+use std::collections::{btree_map, BTreeMap};
 use std::ops::{Deref, DerefMut};
 
 use rustc_serialize::base64::{FromBase64, ToBase64};
 
 use serde;
-use serde::de::Error;
 
 use sodiumoxide::crypto::sign;
 
 use UNPADDED_BASE64;
+use algorithm::KeyId;
+use error::Error;
 
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -51,14 +53,405 @@ impl serde::Deserialize for Base64Signature {
     fn deserialize<D>(deserializer: &mut D) -> Result<Base64Signature, D::Error>
         where D: serde::Deserializer
     {
-        let de_string: String = try!(String::deserialize(deserializer));
+        match try!(<SignatureEntry as serde::Deserialize>::deserialize(deserializer)) {
+            SignatureEntry::Encoded(b64) => {
+                decode_signature(&b64)
+                    .map(Base64Signature)
+                    .map_err(|err| serde::de::Error::invalid_value(&err.to_string()))
+            }
+            SignatureEntry::WrongType(found) => {
+                Err(serde::de::Error::invalid_value(&format!("Expected signature string, found {}",
+                                                             found)))
+            }
+        }
+    }
+}
+
+
+/// Decode an unpadded base64 signature.
+pub fn decode_signature(b64: &str) -> Result<sign::Signature, Error> {
+    let slice = try!(b64.from_base64().map_err(|_| Error::Base64Decode));
+    sign::Signature::from_slice(&slice).ok_or(Error::InvalidSignatureLength {
+        expected: sign::SIGNATUREBYTES,
+        found: slice.len(),
+    })
+}
+
+
+/// Whether signatures by the key ID are ed25519 signatures. Key IDs without an
+/// algorithm prefix are assumed to be.
+fn is_ed25519_key_id(key_id: &str) -> bool {
+    KeyId::parse(key_id).map_or(true, |key_id| key_id.algorithm == "ed25519")
+}
+
+
+/// A single value in a signatures block, which may not be a signature.
+enum SignatureEntry {
+    /// A string, which should be a base64 encoded signature.
+    Encoded(String),
+    /// A value that isn't a string, with the JSON type that was found instead.
+    WrongType(&'static str),
+}
+
+impl serde::Deserialize for SignatureEntry {
+    fn deserialize<D>(deserializer: &mut D) -> Result<SignatureEntry, D::Error>
+        where D: serde::Deserializer
+    {
+        deserializer.deserialize(SignatureEntryVisitor)
+    }
+}
+
+struct SignatureEntryVisitor;
+impl serde::de::Visitor for SignatureEntryVisitor {
+    type Value = SignatureEntry;
+
+    fn visit_str<E>(&mut self, value: &str) -> Result<SignatureEntry, E>
+        where E: serde::de::Error
+    {
+        Ok(SignatureEntry::Encoded(value.to_string()))
+    }
+
+    fn visit_bool<E>(&mut self, _: bool) -> Result<SignatureEntry, E>
+        where E: serde::de::Error
+    {
+        Ok(SignatureEntry::WrongType("boolean"))
+    }
+
+    fn visit_i64<E>(&mut self, _: i64) -> Result<SignatureEntry, E>
+        where E: serde::de::Error
+    {
+        Ok(SignatureEntry::WrongType("number"))
+    }
+
+    fn visit_u64<E>(&mut self, _: u64) -> Result<SignatureEntry, E>
+        where E: serde::de::Error
+    {
+        Ok(SignatureEntry::WrongType("number"))
+    }
+
+    fn visit_f64<E>(&mut self, _: f64) -> Result<SignatureEntry, E>
+        where E: serde::de::Error
+    {
+        Ok(SignatureEntry::WrongType("number"))
+    }
+
+    fn visit_unit<E>(&mut self) -> Result<SignatureEntry, E>
+        where E: serde::de::Error
+    {
+        Ok(SignatureEntry::WrongType("null"))
+    }
+
+    fn visit_none<E>(&mut self) -> Result<SignatureEntry, E>
+        where E: serde::de::Error
+    {
+        Ok(SignatureEntry::WrongType("null"))
+    }
+
+    fn visit_seq<V>(&mut self, mut visitor: V) -> Result<SignatureEntry, V::Error>
+        where V: serde::de::SeqVisitor
+    {
+        while let Some(_) = try!(visitor.visit::<serde::de::impls::IgnoredAny>()) {}
+        try!(visitor.end());
+        Ok(SignatureEntry::WrongType("array"))
+    }
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<SignatureEntry, V::Error>
+        where V: serde::de::MapVisitor
+    {
+        while let Some(_) = try!(visitor.visit::<serde::de::impls::IgnoredAny,
+                                                 serde::de::impls::IgnoredAny>()) {}
+        try!(visitor.end());
+        Ok(SignatureEntry::WrongType("object"))
+    }
+}
+
+
+/// The contents of a `signatures` block, i.e. a map from entity to key ID to
+/// signature.
+///
+/// Unlike deserializing a plain `BTreeMap`, errors report the entity and key
+/// ID of the offending signature.
+///
+/// The map derefs to, and its typed accessors and iterators cover, the ed25519
+/// signatures. Signatures by key IDs of other algorithms are kept as raw bytes,
+/// see `get_raw_signature`, so that they survive a round trip and can be
+/// checked with `algorithm::Algorithms::verify_signed`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SignatureMap {
+    ed25519: BTreeMap<String, BTreeMap<String, Base64Signature>>,
+    other: BTreeMap<String, BTreeMap<String, Vec<u8>>>,
+}
+
+impl SignatureMap {
+    pub fn new() -> SignatureMap {
+        SignatureMap::default()
+    }
+
+    /// The ed25519 signatures, dropping any others.
+    pub fn into_inner(self) -> BTreeMap<String, BTreeMap<String, Base64Signature>> {
+        self.ed25519
+    }
+
+    pub fn get_signature(&self, entity: &str, key_id: &str) -> Option<&sign::Signature> {
+        self.ed25519.get(entity).and_then(|sigs| sigs.get(key_id)).map(|sig| &sig.0)
+    }
+
+    /// The bytes of a signature by any algorithm.
+    pub fn get_raw_signature(&self, entity: &str, key_id: &str) -> Option<&[u8]> {
+        match self.get_signature(entity, key_id) {
+            Some(sig) => Some(&sig.0[..]),
+            None => {
+                self.other.get(entity).and_then(|sigs| sigs.get(key_id)).map(|sig| &sig[..])
+            }
+        }
+    }
+
+    /// The signatures by key IDs of algorithms other than ed25519, as raw
+    /// bytes by entity and key ID.
+    pub fn get_other_signatures(&self) -> &BTreeMap<String, BTreeMap<String, Vec<u8>>> {
+        &self.other
+    }
+
+    /// Iterate over every signature as `(entity, key_id, signature)`.
+    pub fn get_signatures(&self) -> SignatureIter {
+        SignatureIter {
+            entities: self.ed25519.iter(),
+            current: None,
+        }
+    }
+
+    /// Iterate over the signatures of a single entity as `(key_id, signature)`.
+    pub fn get_signatures_for_entity(&self, entity: &str) -> EntitySignatureIter {
+        EntitySignatureIter(self.ed25519.get(entity).map(|sigs| sigs.iter()))
+    }
+
+    pub fn get_entities(&self) -> EntityIter {
+        EntityIter(self.ed25519.keys())
+    }
+
+    pub fn add_signature(&mut self, entity: &str, key_id: &str, sig: sign::Signature) {
+        self.ed25519
+            .entry(entity.to_string())
+            .or_insert_with(BTreeMap::new)
+            .insert(key_id.to_string(), Base64Signature(sig));
+    }
+
+    /// Add a signature by any algorithm. Signatures by ed25519 key IDs have
+    /// to be the right length.
+    pub fn add_raw_signature(&mut self,
+                             entity: &str,
+                             key_id: &str,
+                             sig: &[u8])
+                             -> Result<(), Error> {
+        if is_ed25519_key_id(key_id) {
+            let sig = try!(sign::Signature::from_slice(sig).ok_or(Error::InvalidSignatureLength {
+                expected: sign::SIGNATUREBYTES,
+                found: sig.len(),
+            }));
+            self.add_signature(entity, key_id, sig);
+        } else {
+            self.other
+                .entry(entity.to_string())
+                .or_insert_with(BTreeMap::new)
+                .insert(key_id.to_string(), sig.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Remove every signature, of every algorithm.
+    pub fn clear(&mut self) {
+        self.ed25519.clear();
+        self.other.clear();
+    }
+
+    /// Add all the signatures in `other`, replacing any existing signatures
+    /// with the same entity and key ID.
+    pub fn merge(&mut self, other: &SignatureMap) {
+        for (entity, sigs) in &other.ed25519 {
+            let entity_sigs = self.ed25519.entry(entity.clone()).or_insert_with(BTreeMap::new);
+            for (key_id, sig) in sigs {
+                entity_sigs.insert(key_id.clone(), sig.clone());
+            }
+        }
+        for (entity, sigs) in &other.other {
+            let entity_sigs = self.other.entry(entity.clone()).or_insert_with(BTreeMap::new);
+            for (key_id, sig) in sigs {
+                entity_sigs.insert(key_id.clone(), sig.clone());
+            }
+        }
+    }
+
+    /// Build the map from deserialized entries. If `lenient` is set entries
+    /// that aren't strings are dropped, otherwise they're an error.
+    fn from_entries(entries: BTreeMap<String, BTreeMap<String, SignatureEntry>>,
+                    lenient: bool)
+                    -> Result<SignatureMap, String> {
+        let mut map = SignatureMap::new();
+        for (entity, sigs) in entries {
+            let mut entity_sigs = BTreeMap::new();
+            let mut other_sigs = BTreeMap::new();
+            for (key_id, entry) in sigs {
+                match entry {
+                    SignatureEntry::Encoded(b64) => {
+                        let decoded = if is_ed25519_key_id(&key_id) {
+                            decode_signature(&b64).map(|sig| {
+                                entity_sigs.insert(key_id.clone(), Base64Signature(sig));
+                            })
+                        } else {
+                            b64.from_base64().map_err(|_| Error::Base64Decode).map(|sig| {
+                                other_sigs.insert(key_id.clone(), sig);
+                            })
+                        };
+                        if let Err(err) = decoded {
+                            return Err(format!("Invalid signature for {} {}: {}",
+                                               entity,
+                                               key_id,
+                                               err));
+                        }
+                    }
+                    SignatureEntry::WrongType(_) if lenient => {}
+                    SignatureEntry::WrongType(found) => {
+                        return Err(format!("Expected signature string for {} {}, found {}",
+                                           entity,
+                                           key_id,
+                                           found));
+                    }
+                }
+            }
+            if !other_sigs.is_empty() {
+                map.other.insert(entity.clone(), other_sigs);
+            }
+            map.ed25519.insert(entity, entity_sigs);
+        }
+        Ok(map)
+    }
+}
+
+/// Iterator returned by `SignatureMap::get_signatures`.
+pub struct SignatureIter<'a> {
+    entities: btree_map::Iter<'a, String, BTreeMap<String, Base64Signature>>,
+    current: Option<(&'a str, btree_map::Iter<'a, String, Base64Signature>)>,
+}
+
+impl<'a> Iterator for SignatureIter<'a> {
+    type Item = (&'a str, &'a str, &'a sign::Signature);
 
-        let sig = try!(de_string.from_base64()
-                                .ok()
-                                .and_then(|slice| sign::Signature::from_slice(&slice))
-                                .ok_or_else(|| D::Error::invalid_value("Invalid signature")));
+    fn next(&mut self) -> Option<(&'a str, &'a str, &'a sign::Signature)> {
+        loop {
+            if let Some((entity, ref mut sigs)) = self.current {
+                if let Some((key_id, sig)) = sigs.next() {
+                    return Some((entity, key_id, &sig.0));
+                }
+            }
+            match self.entities.next() {
+                Some((entity, sigs)) => self.current = Some((entity, sigs.iter())),
+                None => return None,
+            }
+        }
+    }
+}
+
+/// Iterator returned by `SignatureMap::get_signatures_for_entity`.
+pub struct EntitySignatureIter<'a>(Option<btree_map::Iter<'a, String, Base64Signature>>);
+
+impl<'a> Iterator for EntitySignatureIter<'a> {
+    type Item = (&'a str, &'a sign::Signature);
+
+    fn next(&mut self) -> Option<(&'a str, &'a sign::Signature)> {
+        self.0.as_mut().and_then(|sigs| sigs.next()).map(|(key_id, sig)| (&key_id[..], &sig.0))
+    }
+}
+
+/// Iterator returned by `SignatureMap::get_entities`.
+pub struct EntityIter<'a>(btree_map::Keys<'a, String, BTreeMap<String, Base64Signature>>);
+
+impl<'a> Iterator for EntityIter<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.0.next().map(|entity| &entity[..])
+    }
+}
+
+
+impl Deref for SignatureMap {
+    type Target = BTreeMap<String, BTreeMap<String, Base64Signature>>;
+
+    fn deref(&self) -> &BTreeMap<String, BTreeMap<String, Base64Signature>> {
+        &self.ed25519
+    }
+}
+
+impl DerefMut for SignatureMap {
+    fn deref_mut(&mut self) -> &mut BTreeMap<String, BTreeMap<String, Base64Signature>> {
+        &mut self.ed25519
+    }
+}
+
+impl From<BTreeMap<String, BTreeMap<String, Base64Signature>>> for SignatureMap {
+    fn from(map: BTreeMap<String, BTreeMap<String, Base64Signature>>) -> SignatureMap {
+        SignatureMap {
+            ed25519: map,
+            other: BTreeMap::new(),
+        }
+    }
+}
+
+impl serde::Serialize for SignatureMap {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        if self.other.is_empty() {
+            return serde::Serialize::serialize(&self.ed25519, serializer);
+        }
+
+        let mut encoded: BTreeMap<&str, BTreeMap<&str, String>> = BTreeMap::new();
+        for (entity, sigs) in &self.ed25519 {
+            let entity_sigs = encoded.entry(entity).or_insert_with(BTreeMap::new);
+            for (key_id, sig) in sigs {
+                entity_sigs.insert(key_id, sig.0[..].to_base64(UNPADDED_BASE64));
+            }
+        }
+        for (entity, sigs) in &self.other {
+            let entity_sigs = encoded.entry(entity).or_insert_with(BTreeMap::new);
+            for (key_id, sig) in sigs {
+                entity_sigs.insert(key_id, sig.to_base64(UNPADDED_BASE64));
+            }
+        }
+        serde::Serialize::serialize(&encoded, serializer)
+    }
+}
+
+impl serde::Deserialize for SignatureMap {
+    fn deserialize<D>(deserializer: &mut D) -> Result<SignatureMap, D::Error>
+        where D: serde::Deserializer
+    {
+        let entries = try!(serde::Deserialize::deserialize(deserializer));
+        SignatureMap::from_entries(entries, false)
+            .map_err(|e| serde::de::Error::invalid_value(&e))
+    }
+}
+
+
+/// A `SignatureMap` that skips entries that aren't strings when deserializing,
+/// rather than failing.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LenientSignatureMap(pub SignatureMap);
+
+impl From<LenientSignatureMap> for SignatureMap {
+    fn from(map: LenientSignatureMap) -> SignatureMap {
+        map.0
+    }
+}
 
-        Ok(Base64Signature(sig))
+impl serde::Deserialize for LenientSignatureMap {
+    fn deserialize<D>(deserializer: &mut D) -> Result<LenientSignatureMap, D::Error>
+        where D: serde::Deserializer
+    {
+        let entries = try!(serde::Deserialize::deserialize(deserializer));
+        SignatureMap::from_entries(entries, true)
+            .map(LenientSignatureMap)
+            .map_err(|e| serde::de::Error::invalid_value(&e))
     }
 }
 
@@ -66,6 +459,7 @@ impl serde::Deserialize for Base64Signature {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeMap;
     use sodiumoxide::crypto::sign;
 
     use serde_json;
@@ -91,4 +485,108 @@ mod tests {
 
         assert_eq!(de_sig, expected_sig);
     }
+
+    #[test]
+    fn deserialize_wrong_type() {
+        let err = serde_json::from_str::<Base64Signature>("true").unwrap_err();
+        assert!(format!("{}", err).contains("found boolean"));
+    }
+
+    #[test]
+    fn signature_map_wrong_type() {
+        let serialized = r#"{"jki.re":{"ed25519:auto":"X2t7jN0jaJsiZWp57da9GqmQ874QFbukCMSqc5VclaB+2n4i8LPcZDkD6+fzg4tkfpSsiIDogkY4HWv1cnGhAg","ed25519:other":12}}"#;
+
+        let err = serde_json::from_str::<SignatureMap>(serialized).unwrap_err();
+        let msg = format!("{}", err);
+        assert!(msg.contains("jki.re ed25519:other"), "{}", msg);
+        assert!(msg.contains("found number"), "{}", msg);
+
+        let lenient: SignatureMap = serde_json::from_str::<LenientSignatureMap>(serialized)
+                                        .unwrap()
+                                        .into();
+        assert_eq!(lenient["jki.re"].len(), 1);
+        assert!(lenient["jki.re"].contains_key("ed25519:auto"));
+    }
+
+    #[test]
+    fn signature_map_malformed() {
+        let serialized = r#"{"jki.re":{"ed25519:auto":"not base64!"}}"#;
+
+        let err = serde_json::from_str::<LenientSignatureMap>(serialized).unwrap_err();
+        let msg = format!("{}", err);
+        assert!(msg.contains("jki.re ed25519:auto"), "{}", msg);
+        assert!(msg.contains("Invalid base64"), "{}", msg);
+
+        let err = serde_json::from_str::<SignatureMap>(r#"{"jki.re":{"ed25519:auto":"AAAA"}}"#)
+                      .unwrap_err();
+        let msg = format!("{}", err);
+        assert!(msg.contains("expected 64 bytes, found 3"), "{}", msg);
+    }
+
+    #[test]
+    fn signature_map_merge() {
+        let sig_bytes = b"_k{\x8c\xdd#h\x9b\"ejy\xed\xd6\xbd\x1a\xa9\x90\xf3\xbe\x10\x15\xbb\xa4\x08\xc4\xaas\x95\\\x95\xa0~\xda~\"\xf0\xb3\xdcd9\x03\xeb\xe7\xf3\x83\x8bd~\x94\xac\x88\x80\xe8\x82F8\x1dk\xf5rq\xa1\x02";
+        let sig = sign::Signature::from_slice(sig_bytes).unwrap();
+
+        let mut map = SignatureMap::new();
+        map.add_signature("jki.re", "ed25519:auto", sig);
+
+        let mut other = SignatureMap::new();
+        other.add_signature("jki.re", "ed25519:other", sig);
+        other.add_signature("example.com", "ed25519:auto", sig);
+
+        map.merge(&other);
+        assert_eq!(map.get_signature("jki.re", "ed25519:auto"), Some(&sig));
+        assert_eq!(map.get_signature("jki.re", "ed25519:other"), Some(&sig));
+        assert_eq!(map.get_signature("example.com", "ed25519:auto"), Some(&sig));
+        assert_eq!(map.get_signature("example.com", "ed25519:other"), None);
+    }
+
+    #[test]
+    fn signature_map_iter() {
+        let sig_bytes = b"_k{\x8c\xdd#h\x9b\"ejy\xed\xd6\xbd\x1a\xa9\x90\xf3\xbe\x10\x15\xbb\xa4\x08\xc4\xaas\x95\\\x95\xa0~\xda~\"\xf0\xb3\xdcd9\x03\xeb\xe7\xf3\x83\x8bd~\x94\xac\x88\x80\xe8\x82F8\x1dk\xf5rq\xa1\x02";
+        let sig = sign::Signature::from_slice(sig_bytes).unwrap();
+
+        let mut map = SignatureMap::new();
+        assert_eq!(map.get_signatures().count(), 0);
+
+        map.add_signature("jki.re", "ed25519:b", sig);
+        map.add_signature("jki.re", "ed25519:a", sig);
+        map.add_signature("example.com", "ed25519:auto", sig);
+        map.insert("empty.org".to_string(), BTreeMap::new());
+
+        assert_eq!(map.get_signatures().collect::<Vec<_>>(),
+                   vec![("example.com", "ed25519:auto", &sig),
+                        ("jki.re", "ed25519:a", &sig),
+                        ("jki.re", "ed25519:b", &sig)]);
+        assert_eq!(map.get_signatures_for_entity("jki.re").collect::<Vec<_>>(),
+                   vec![("ed25519:a", &sig), ("ed25519:b", &sig)]);
+        assert_eq!(map.get_signatures_for_entity("example.org").count(), 0);
+        assert_eq!(map.get_entities().collect::<Vec<_>>(),
+                   vec!["empty.org", "example.com", "jki.re"]);
+    }
+
+    #[test]
+    fn signature_map_other_algorithms() {
+        let serialized = r#"{"jki.re":{"curve25519:a":"AAAA","ed25519:auto":"X2t7jN0jaJsiZWp57da9GqmQ874QFbukCMSqc5VclaB+2n4i8LPcZDkD6+fzg4tkfpSsiIDogkY4HWv1cnGhAg"}}"#;
+
+        let mut map: SignatureMap = serde_json::from_str(serialized).unwrap();
+        assert_eq!(map.get_raw_signature("jki.re", "curve25519:a"), Some(&[0, 0, 0][..]));
+        assert_eq!(map.get_raw_signature("jki.re", "ed25519:auto").map(|sig| sig.len()),
+                   Some(64));
+        assert_eq!(map.get_signature("jki.re", "curve25519:a"), None);
+        assert_eq!(map.get_signatures().count(), 1);
+        assert_eq!(serde_json::to_string(&map).unwrap(), serialized);
+
+        map.add_raw_signature("example.com", "rsa:1", b"sig").unwrap();
+        assert_eq!(map.get_raw_signature("example.com", "rsa:1"), Some(&b"sig"[..]));
+        assert!(map.add_raw_signature("example.com", "ed25519:1", b"sig").is_err());
+
+        map.clear();
+        assert_eq!(serde_json::to_string(&map).unwrap(), "{}");
+
+        let err = serde_json::from_str::<SignatureMap>(r#"{"jki.re":{"curve25519:a":"!"}}"#)
+                      .unwrap_err();
+        assert!(format!("{}", err).contains("jki.re curve25519:a"));
+    }
 }
@@ -0,0 +1,215 @@
+//! Parsing JSON while noting the things that canonical JSON forbids but
+//! `serde_json::Value` silently accepts.
+
+use std::collections::BTreeMap;
+
+use serde;
+use serde_json::Value;
+
+
+/// The largest integer magnitude allowed in canonical JSON, i.e. 2^53 - 1.
+pub const MAX_SAFE_INTEGER: u64 = 9007199254740991;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IssueKind {
+    Float,
+    IntegerOutOfRange,
+    DuplicateKey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub kind: IssueKind,
+    /// Path segments from the value up to the root, i.e. reversed.
+    path: Vec<String>,
+}
+
+impl Issue {
+    fn new(kind: IssueKind) -> Issue {
+        Issue {
+            kind: kind,
+            path: Vec::new(),
+        }
+    }
+
+    /// The location of the issue as a JSON pointer, e.g. `/a/0`.
+    pub fn pointer(&self) -> String {
+        let mut pointer = String::new();
+        for segment in self.path.iter().rev() {
+            pointer.push('/');
+            pointer.push_str(&segment.replace("~", "~0").replace("/", "~1"));
+        }
+        pointer
+    }
+}
+
+
+/// A parsed value along with every issue found in it, in document order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StrictValue {
+    pub value: Value,
+    pub issues: Vec<Issue>,
+}
+
+impl StrictValue {
+    fn new(value: Value) -> StrictValue {
+        StrictValue {
+            value: value,
+            issues: Vec::new(),
+        }
+    }
+
+    fn with_issue(value: Value, kind: IssueKind) -> StrictValue {
+        StrictValue {
+            value: value,
+            issues: vec![Issue::new(kind)],
+        }
+    }
+
+    fn check_integer(value: Value, magnitude: u64) -> StrictValue {
+        if magnitude > MAX_SAFE_INTEGER {
+            StrictValue::with_issue(value, IssueKind::IntegerOutOfRange)
+        } else {
+            StrictValue::new(value)
+        }
+    }
+}
+
+impl serde::Deserialize for StrictValue {
+    fn deserialize<D>(deserializer: &mut D) -> Result<StrictValue, D::Error>
+        where D: serde::Deserializer
+    {
+        deserializer.deserialize(StrictValueVisitor)
+    }
+}
+
+struct StrictValueVisitor;
+impl serde::de::Visitor for StrictValueVisitor {
+    type Value = StrictValue;
+
+    fn visit_bool<E>(&mut self, value: bool) -> Result<StrictValue, E>
+        where E: serde::de::Error
+    {
+        Ok(StrictValue::new(Value::Bool(value)))
+    }
+
+    fn visit_i64<E>(&mut self, value: i64) -> Result<StrictValue, E>
+        where E: serde::de::Error
+    {
+        let magnitude = if value < 0 {
+            (!(value as u64)).wrapping_add(1)
+        } else {
+            value as u64
+        };
+        Ok(StrictValue::check_integer(Value::I64(value), magnitude))
+    }
+
+    fn visit_u64<E>(&mut self, value: u64) -> Result<StrictValue, E>
+        where E: serde::de::Error
+    {
+        Ok(StrictValue::check_integer(Value::U64(value), value))
+    }
+
+    fn visit_f64<E>(&mut self, value: f64) -> Result<StrictValue, E>
+        where E: serde::de::Error
+    {
+        Ok(StrictValue::with_issue(Value::F64(value), IssueKind::Float))
+    }
+
+    fn visit_str<E>(&mut self, value: &str) -> Result<StrictValue, E>
+        where E: serde::de::Error
+    {
+        Ok(StrictValue::new(Value::String(value.to_string())))
+    }
+
+    fn visit_string<E>(&mut self, value: String) -> Result<StrictValue, E>
+        where E: serde::de::Error
+    {
+        Ok(StrictValue::new(Value::String(value)))
+    }
+
+    fn visit_unit<E>(&mut self) -> Result<StrictValue, E>
+        where E: serde::de::Error
+    {
+        Ok(StrictValue::new(Value::Null))
+    }
+
+    fn visit_none<E>(&mut self) -> Result<StrictValue, E>
+        where E: serde::de::Error
+    {
+        Ok(StrictValue::new(Value::Null))
+    }
+
+    fn visit_seq<V>(&mut self, mut visitor: V) -> Result<StrictValue, V::Error>
+        where V: serde::de::SeqVisitor
+    {
+        let mut values = Vec::new();
+        let mut issues = Vec::new();
+        while let Some(elem) = try!(visitor.visit::<StrictValue>()) {
+            let idx = values.len().to_string();
+            issues.extend(elem.issues.into_iter().map(|mut issue| {
+                issue.path.push(idx.clone());
+                issue
+            }));
+            values.push(elem.value);
+        }
+        try!(visitor.end());
+
+        Ok(StrictValue {
+            value: Value::Array(values),
+            issues: issues,
+        })
+    }
+
+    fn visit_map<V>(&mut self, mut visitor: V) -> Result<StrictValue, V::Error>
+        where V: serde::de::MapVisitor
+    {
+        let mut values = BTreeMap::new();
+        let mut issues = Vec::new();
+        while let Some(key) = try!(visitor.visit_key::<String>()) {
+            let elem: StrictValue = try!(visitor.visit_value());
+            issues.extend(elem.issues.into_iter().map(|mut issue| {
+                issue.path.push(key.clone());
+                issue
+            }));
+            if values.insert(key.clone(), elem.value).is_some() {
+                let mut issue = Issue::new(IssueKind::DuplicateKey);
+                issue.path.push(key);
+                issues.push(issue);
+            }
+        }
+        try!(visitor.end());
+
+        Ok(StrictValue {
+            value: Value::Object(values),
+            issues: issues,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json;
+
+    #[test]
+    fn issues() {
+        let parsed: StrictValue =
+            serde_json::from_str(r#"{"a/b":[1,2.5,-9007199254740992],"c":{"d":1,"d":2},"e":9007199254740991}"#)
+                .unwrap();
+
+        let found: Vec<(IssueKind, String)> = parsed.issues
+                                                    .iter()
+                                                    .map(|i| (i.kind, i.pointer()))
+                                                    .collect();
+        assert_eq!(found,
+                   vec![(IssueKind::Float, "/a~1b/1".to_string()),
+                        (IssueKind::IntegerOutOfRange, "/a~1b/2".to_string()),
+                        (IssueKind::DuplicateKey, "/c/d".to_string())]);
+
+        assert_eq!(parsed.value.find("c").and_then(|c| c.find("d")).and_then(|d| d.as_u64()),
+                   Some(2));
+    }
+}
@@ -8,7 +8,8 @@ use serde_json;
 
 use sodiumoxide::crypto::sign;
 
-use ser::signatures::Base64Signature;
+use ser::encode_canonically;
+use ser::signatures::SignatureMap;
 
 
 pub trait Signatures {
@@ -29,11 +30,23 @@ pub trait SignaturesMut {
 }
 
 pub trait Signed {
-    fn signatures(&self) -> &Signatures;
+    fn signatures(&self) -> &SignatureMap;
+
+    /// Access the signatures as a trait object.
+    #[cfg(feature = "dyn-signatures")]
+    fn signatures_dyn(&self) -> &Signatures {
+        self.signatures()
+    }
 }
 
 pub trait SignedMut: Signed {
-    fn signatures_mut(&mut self) -> &mut SignaturesMut;
+    fn signatures_mut(&mut self) -> &mut SignatureMap;
+
+    /// Access the signatures mutably as a trait object.
+    #[cfg(feature = "dyn-signatures")]
+    fn signatures_mut_dyn(&mut self) -> &mut SignaturesMut {
+        self.signatures_mut()
+    }
 }
 
 pub trait AsCanonical {
@@ -45,6 +58,30 @@ pub trait GetUnsigned {
 }
 
 
+// Used by `signed_struct!`, which can't name `serde_json` itself.
+#[doc(hidden)]
+pub type JsonValue = serde_json::Value;
+
+#[doc(hidden)]
+pub fn unsigned_to_value<U>(unsigned: &Option<U>) -> Option<serde_json::Value>
+    where U: serde::Serialize
+{
+    unsigned.as_ref().map(serde_json::to_value)
+}
+
+/// The canonical form of a struct for `signed_struct!`.
+///
+/// # Panics
+///
+/// If the struct fails to serialize, see `signed_struct!`.
+#[doc(hidden)]
+pub fn canonical_of<T>(obj: &T) -> Cow<[u8]>
+    where T: serde::Serialize
+{
+    Cow::Owned(encode_canonically(obj).expect("signed struct failed to serialize"))
+}
+
+
 impl<S> Signatures for BTreeMap<String, BTreeMap<String, S>>
     where S: Deref<Target = sign::Signature>
 {
@@ -89,6 +126,34 @@ impl<S> Signatures for BTreeMap<String, BTreeMap<String, S>>
     }
 }
 
+// The inherent methods return concrete iterators, so only use of `SignatureMap`
+// as a trait object pays for the boxing.
+impl Signatures for SignatureMap {
+    fn get_signature(&self, entity: &str, key_id: &str) -> Option<&sign::Signature> {
+        SignatureMap::get_signature(self, entity, key_id)
+    }
+
+    fn get_signatures_for_entity<'a>(
+        &'a self,
+        entity: &'a str
+    ) -> Box<Iterator<Item = (&'a str, &sign::Signature)> + 'a> {
+        Box::new(SignatureMap::get_signatures_for_entity(self, entity))
+    }
+
+    fn get_signatures<'a>(&'a self)
+        -> Box<Iterator<Item = (&'a str, &'a str, &sign::Signature)> + 'a> {
+        Box::new(SignatureMap::get_signatures(self))
+    }
+
+    fn get_entities<'a>(&'a self) -> Box<Iterator<Item = &'a str> + 'a> {
+        Box::new(SignatureMap::get_entities(self))
+    }
+
+    fn as_map<'a>(&'a self) -> BTreeMap<&'a str, BTreeMap<&'a str, &'a sign::Signature>> {
+        Signatures::as_map(&**self)
+    }
+}
+
 impl<S> SignaturesMut for BTreeMap<String, BTreeMap<String, S>>
     where S: From<sign::Signature>
 {
@@ -103,20 +168,31 @@ impl<S> SignaturesMut for BTreeMap<String, BTreeMap<String, S>>
     }
 }
 
+impl SignaturesMut for SignatureMap {
+    fn add_signature(&mut self, entity: &str, key_id: &str, sig: sign::Signature) {
+        SignatureMap::add_signature(self, entity, key_id, sig)
+    }
+
+    fn clear(&mut self) {
+        SignatureMap::clear(self)
+    }
+}
+
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct SimpleSigned {
-    pub signatures: BTreeMap<String, BTreeMap<String, Base64Signature>>,
+    /// The object's signatures, by entity and key ID.
+    pub signatures: SignatureMap,
 }
 
 impl Signed for SimpleSigned {
-    fn signatures(&self) -> &Signatures {
+    fn signatures(&self) -> &SignatureMap {
         &self.signatures
     }
 }
 
 impl SignedMut for SimpleSigned {
-    fn signatures_mut(&mut self) -> &mut SignaturesMut {
+    fn signatures_mut(&mut self) -> &mut SignatureMap {
         &mut self.signatures
     }
 }
@@ -211,7 +287,7 @@ impl<D: serde::de::Deserializer> serde::de::Visitor for SimpleSignedVisitor<D> {
                 None => {
                     match visitor.missing_field("signatures") {
                         Ok(value) => value,
-                        Err(_) => BTreeMap::new(),
+                        Err(_) => SignatureMap::new(),
                     }
                 }
             };
@@ -271,5 +347,10 @@ mod tests {
 
         let entities = &map.get_signatures().collect_vec();
         assert_eq!(&entities[..], &[("jki.re", "ed25519:auto", &expected_sig)]);
+
+        // Code written against the old type of `SimpleSigned::signatures`.
+        let simple = SimpleSigned { signatures: SignatureMap::from(map.clone()) };
+        assert_eq!(*simple.signatures, map);
+        assert_eq!(simple.signatures.get("jki.re").map(|sigs| sigs.len()), Some(1));
     }
 }
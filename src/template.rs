@@ -0,0 +1,233 @@
+//! Signing documents with top level placeholder fields that are left out of
+//! the canonical form, so they can be filled in after signing without
+//! invalidating the signatures, e.g. routing metadata in a pre-signed
+//! response.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::error;
+use std::fmt;
+
+use serde_json;
+
+use error::Error;
+use keys::{NamedPublicKey, VerifyResult};
+use ser::{UNHASHED_KEYS, UNSIGNED_KEYS};
+use ser::encode_canonically_stripping;
+use ser::signatures::SignatureMap;
+use signed::{AsCanonical, Signed, SignedMut};
+use value::SignedValue;
+
+
+#[derive(Debug)]
+pub enum TemplateError {
+    /// A field that isn't one of the template's placeholders was given.
+    NotAPlaceholder(String),
+    /// A placeholder was declared for `signatures`, `unsigned` or `hashes`,
+    /// which have their own meaning.
+    ReservedPlaceholder(String),
+    /// The document isn't a JSON object with a well formed signatures block.
+    Malformed(Error),
+}
+
+impl From<Error> for TemplateError {
+    fn from(err: Error) -> TemplateError {
+        TemplateError::Malformed(err)
+    }
+}
+
+impl From<serde_json::Error> for TemplateError {
+    fn from(err: serde_json::Error) -> TemplateError {
+        TemplateError::Malformed(Error::Json(err))
+    }
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TemplateError::NotAPlaceholder(ref field) => {
+                write!(f, "{} isn't a placeholder", field)
+            }
+            TemplateError::ReservedPlaceholder(ref field) => {
+                write!(f, "{} can't be a placeholder", field)
+            }
+            TemplateError::Malformed(ref err) => write!(f, "Malformed document: {}", err),
+        }
+    }
+}
+
+impl error::Error for TemplateError {
+    fn description(&self) -> &str {
+        match *self {
+            TemplateError::NotAPlaceholder(_) => "not a placeholder",
+            TemplateError::ReservedPlaceholder(_) => "reserved placeholder",
+            TemplateError::Malformed(_) => "malformed document",
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            TemplateError::Malformed(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+
+/// A document to sign whose placeholder fields aren't covered by the
+/// signatures.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    value: SignedValue,
+    placeholders: Vec<String>,
+}
+
+impl Template {
+    pub fn new(value: SignedValue, placeholders: Vec<String>) -> Result<Template, TemplateError> {
+        if let Some(reserved) = placeholders.iter().find(|p| UNHASHED_KEYS.contains(&&p[..])) {
+            return Err(TemplateError::ReservedPlaceholder(reserved.clone()));
+        }
+        Ok(Template {
+            value: value,
+            placeholders: placeholders,
+        })
+    }
+
+    pub fn from_slice(bytes: &[u8], placeholders: Vec<String>) -> Result<Template, TemplateError> {
+        let value = try!(SignedValue::from_slice(bytes));
+        Template::new(value, placeholders)
+    }
+
+    pub fn placeholders(&self) -> &[String] {
+        &self.placeholders
+    }
+
+    pub fn value(&self) -> &SignedValue {
+        &self.value
+    }
+
+    /// Fill in placeholder fields, keeping the template's signatures. Any
+    /// placeholders not given keep their value in the template, if any.
+    pub fn fill(&self, fields: BTreeMap<String, serde_json::Value>)
+                -> Result<SignedValue, TemplateError> {
+        let mut val = self.value.to_value();
+        if let Some(obj) = val.as_object_mut() {
+            for (key, field) in fields {
+                if !self.placeholders.contains(&key) {
+                    return Err(TemplateError::NotAPlaceholder(key));
+                }
+                obj.insert(key, field);
+            }
+        }
+        Ok(try!(SignedValue::from_value(val)))
+    }
+}
+
+impl Signed for Template {
+    fn signatures(&self) -> &SignatureMap {
+        self.value.signatures()
+    }
+}
+
+impl SignedMut for Template {
+    fn signatures_mut(&mut self) -> &mut SignatureMap {
+        self.value.signatures_mut()
+    }
+}
+
+impl AsCanonical for Template {
+    fn as_canonical(&self) -> Cow<[u8]> {
+        canonical_without(&self.value, &self.placeholders)
+    }
+}
+
+
+/// Verify a filled in template against `key`, i.e. check that the signed
+/// document only differs from the template in its placeholder fields.
+pub fn verify_filled<K>(filled: &SignedValue, placeholders: &[String], key: &K) -> VerifyResult
+    where K: NamedPublicKey
+{
+    key.verify(&Filled {
+        value: filled,
+        placeholders: placeholders,
+    })
+}
+
+struct Filled<'a> {
+    value: &'a SignedValue,
+    placeholders: &'a [String],
+}
+
+impl<'a> Signed for Filled<'a> {
+    fn signatures(&self) -> &SignatureMap {
+        self.value.signatures()
+    }
+}
+
+impl<'a> AsCanonical for Filled<'a> {
+    fn as_canonical(&self) -> Cow<[u8]> {
+        canonical_without(self.value, self.placeholders)
+    }
+}
+
+fn canonical_without<'a>(value: &SignedValue, placeholders: &[String]) -> Cow<'a, [u8]> {
+    let mut stripped: Vec<&str> = UNSIGNED_KEYS.to_vec();
+    stripped.extend(placeholders.iter().map(|p| &p[..]));
+    Cow::Owned(encode_canonically_stripping(value.content(), &stripped)
+                   .expect("JSON values always serialize"))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use error::Error;
+    use keys::{NamedSecretKey, SigningKeyPair, VerifyKey, VerifyResult};
+    use serde_json::Value;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn sign_then_fill() {
+        let key = SigningKeyPair::generate("domain", "ed25519:1");
+        let verify_key = VerifyKey::from_signing_key(&key);
+        let placeholders = vec!["route".to_string()];
+
+        let mut template = Template::from_slice(br#"{"body":"hi","route":null}"#,
+                                                placeholders.clone())
+                               .unwrap();
+        key.sign(&mut template);
+
+        let mut fields = BTreeMap::new();
+        fields.insert("route".to_string(), Value::String("eu-1".to_string()));
+        let filled = template.fill(fields).unwrap();
+
+        assert_eq!(filled.content().find("route").and_then(|r| r.as_str()), Some("eu-1"));
+        assert_eq!(verify_filled(&filled, &placeholders, &verify_key), VerifyResult::Valid);
+        // Without the placeholder declared the filled field is covered.
+        assert_eq!(verify_filled(&filled, &[], &verify_key), VerifyResult::Invalid);
+
+        let mut fields = BTreeMap::new();
+        fields.insert("body".to_string(), Value::String("bye".to_string()));
+        match template.fill(fields) {
+            Err(TemplateError::NotAPlaceholder(ref field)) if field == "body" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        match Template::from_slice(br#"{"body":"hi"}"#, vec!["signatures".to_string()]) {
+            Err(TemplateError::ReservedPlaceholder(ref p)) if p == "signatures" => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        match Template::from_slice(b"[]", vec![]) {
+            Err(TemplateError::Malformed(Error::Json(_))) => {}
+            res => panic!("Unexpected result: {:?}", res),
+        }
+
+        let tampered = SignedValue::from_slice(&String::from_utf8(filled.to_vec().unwrap())
+                                                    .unwrap()
+                                                    .replace("\"hi\"", "\"bye\"")
+                                                    .into_bytes())
+                           .unwrap();
+        assert_eq!(verify_filled(&tampered, &placeholders, &verify_key),
+                   VerifyResult::Invalid);
+    }
+}
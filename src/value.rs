@@ -0,0 +1,180 @@
+//! Signing arbitrary JSON objects while keeping every field, for the common
+//! "sign what I was given" case.
+
+use std::borrow::Cow;
+
+use serde;
+use serde_json;
+
+use ser::{CanonicalError, CanonicalOptions, canonicalize_with, encode_canonically,
+          replace_non_finite};
+use ser::signatures::SignatureMap;
+use signed::{AsCanonical, GetUnsigned, Signed, SignedMut};
+
+
+/// A JSON object with its `signatures` and `unsigned` blocks split out, so
+/// that it can be signed and then serialized with all of its original fields.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignedValue {
+    /// The object without `signatures` and `unsigned`.
+    content: serde_json::Value,
+    signatures: SignatureMap,
+    unsigned: Option<serde_json::Value>,
+}
+
+impl SignedValue {
+    /// Split a JSON object. Fails if the value isn't an object or its
+    /// signatures are malformed.
+    pub fn from_value(mut val: serde_json::Value) -> serde_json::Result<SignedValue> {
+        let (signatures, unsigned) = match val.as_object_mut() {
+            Some(obj) => (obj.remove("signatures"), obj.remove("unsigned")),
+            None => {
+                return Err(<serde_json::Error as serde::de::Error>::custom("Expected a JSON \
+                                                                            object"))
+            }
+        };
+
+        let signatures = match signatures {
+            Some(sigs) => try!(serde_json::from_value(sigs)),
+            None => SignatureMap::new(),
+        };
+
+        Ok(SignedValue {
+            content: val,
+            signatures: signatures,
+            unsigned: unsigned,
+        })
+    }
+
+    pub fn from_slice(bytes: &[u8]) -> serde_json::Result<SignedValue> {
+        SignedValue::from_value(try!(serde_json::from_slice(bytes)))
+    }
+
+    /// Like `from_slice`, but refuses input that `canonicalize_with` would
+    /// refuse with `options`, and handles non-finite numbers according to
+    /// `options.non_finite`.
+    pub fn from_slice_with(bytes: &[u8],
+                           options: &CanonicalOptions)
+                           -> Result<SignedValue, CanonicalError> {
+        let input = try!(replace_non_finite(bytes, options.non_finite));
+        try!(canonicalize_with(&input, options));
+        Ok(try!(SignedValue::from_slice(&input)))
+    }
+
+    /// The object without `signatures` and `unsigned`.
+    pub fn content(&self) -> &serde_json::Value {
+        &self.content
+    }
+
+    pub fn unsigned(&self) -> Option<&serde_json::Value> {
+        self.unsigned.as_ref()
+    }
+
+    pub fn set_unsigned(&mut self, unsigned: Option<serde_json::Value>) {
+        self.unsigned = unsigned;
+    }
+
+    /// The full object, with `signatures` and `unsigned` re-attached.
+    pub fn to_value(&self) -> serde_json::Value {
+        let mut val = self.content.clone();
+        if let Some(obj) = val.as_object_mut() {
+            obj.insert(String::from("signatures"), serde_json::to_value(&self.signatures));
+            if let Some(ref unsigned) = self.unsigned {
+                obj.insert(String::from("unsigned"), unsigned.clone());
+            }
+        }
+        val
+    }
+
+    pub fn to_vec(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(&self.to_value())
+    }
+}
+
+impl Signed for SignedValue {
+    fn signatures(&self) -> &SignatureMap {
+        &self.signatures
+    }
+}
+
+impl SignedMut for SignedValue {
+    fn signatures_mut(&mut self) -> &mut SignatureMap {
+        &mut self.signatures
+    }
+}
+
+impl AsCanonical for SignedValue {
+    fn as_canonical(&self) -> Cow<[u8]> {
+        Cow::Owned(encode_canonically(&self.content).expect("JSON values always serialize"))
+    }
+}
+
+impl GetUnsigned for SignedValue {
+    fn get_unsigned(&self) -> Option<serde_json::Value> {
+        self.unsigned.clone()
+    }
+}
+
+impl serde::Serialize for SignedValue {
+    fn serialize<S>(&self, serializer: &mut S) -> Result<(), S::Error>
+        where S: serde::Serializer
+    {
+        serde::Serialize::serialize(&self.to_value(), serializer)
+    }
+}
+
+impl serde::Deserialize for SignedValue {
+    fn deserialize<D>(deserializer: &mut D) -> Result<SignedValue, D::Error>
+        where D: serde::Deserializer
+    {
+        let val: serde_json::Value = try!(serde::Deserialize::deserialize(deserializer));
+        SignedValue::from_value(val).map_err(|e| {
+            <D::Error as serde::de::Error>::invalid_value(&e.to_string())
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use keys::{NamedPublicKey, NamedSecretKey, SigningKeyPair, VerifyKey, VerifyResult};
+    use serde_json;
+    use signed::{AsCanonical, GetUnsigned, Signed};
+
+    #[test]
+    fn sign_preserves_fields() {
+        let bytes = br#"{"old_verify_keys":{},"server_name":"jki.re","signatures":{"jki.re":{"ed25519:auto":"X2t7jN0jaJsiZWp57da9GqmQ874QFbukCMSqc5VclaB+2n4i8LPcZDkD6+fzg4tkfpSsiIDogkY4HWv1cnGhAg"}},"tls_fingerprints":[{"sha256":"Big0aXVWZ/m0oEcHddgP4hTriTEvb4Jx6592W1mB5i4"}],"unsigned":{"age":5},"valid_until_ts":1462110302047,"verify_keys":{"ed25519:auto":{"key":"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI"}}}"#;
+        let mut value = SignedValue::from_slice(bytes).unwrap();
+
+        assert_eq!(&value.as_canonical()[..], &br#"{"old_verify_keys":{},"server_name":"jki.re","tls_fingerprints":[{"sha256":"Big0aXVWZ/m0oEcHddgP4hTriTEvb4Jx6592W1mB5i4"}],"valid_until_ts":1462110302047,"verify_keys":{"ed25519:auto":{"key":"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI"}}}"#[..]);
+        assert_eq!(value.get_unsigned(), Some(serde_json::from_str(r#"{"age":5}"#).unwrap()));
+
+        let notary = SigningKeyPair::generate("notary.example", "ed25519:1");
+        notary.sign(&mut value);
+
+        let reparsed = SignedValue::from_slice(&value.to_vec().unwrap()).unwrap();
+        assert_eq!(reparsed, value);
+        assert!(reparsed.content().find("tls_fingerprints").is_some());
+        assert!(reparsed.signatures().get_signature("jki.re", "ed25519:auto").is_some());
+
+        let key = VerifyKey::from_b64(b"Sr/Vj3FIqyQ2WjJ9fWpUXRdz6fX4oFAjKrDmu198PnI",
+                                      "jki.re",
+                                      "ed25519:auto")
+                      .unwrap();
+        assert_eq!(key.verify(&reparsed), VerifyResult::Valid);
+        assert_eq!(VerifyKey::from_signing_key(&notary).verify(&reparsed),
+                   VerifyResult::Valid);
+    }
+
+    #[test]
+    fn serde() {
+        let value: SignedValue = serde_json::from_str(r#"{"a":[1,2],"unsigned":{}}"#).unwrap();
+        assert_eq!(serde_json::to_string(&value).unwrap(),
+                   r#"{"a":[1,2],"signatures":{},"unsigned":{}}"#);
+
+        assert!(serde_json::from_str::<SignedValue>("[]").is_err());
+        assert!(serde_json::from_str::<SignedValue>(r#"{"signatures":{"a":{"b":1}}}"#)
+                    .is_err());
+    }
+}
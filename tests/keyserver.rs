@@ -0,0 +1,77 @@
+//! Serving a key ring and fetching from it, using only the public API.
+
+#![cfg(feature = "keyserver")]
+
+extern crate signedjson;
+
+use signedjson::keys::{KeyRing, KeyRingError, NamedPublicKey, NamedSecretKey, SigningKeyPair,
+                       VerifyResult, validate_fetched_key_response};
+use signedjson::keyserver::{KeyServer, SERVER_KEYS_PATH};
+use signedjson::value::SignedValue;
+
+const HOUR: u64 = 3600 * 1000;
+
+
+fn fetch(server: &mut KeyServer, now: u64) -> Vec<u8> {
+    server.handle(SERVER_KEYS_PATH, now).unwrap().unwrap().to_vec()
+}
+
+#[test]
+fn fetch_and_verify() {
+    let mut ring = KeyRing::new("example.com");
+    ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:1")).unwrap();
+    let mut server = KeyServer::new(ring, 24 * HOUR);
+
+    let response = fetch(&mut server, 0);
+    let keys = validate_fetched_key_response(&response, "example.com", HOUR, 12 * HOUR)
+                   .unwrap();
+    assert_eq!(keys.verify_keys.len(), 1);
+
+    let mut event = SignedValue::from_slice(br#"{"type":"m.room.message"}"#).unwrap();
+    server.ring().signing_keys()[0].sign(&mut event);
+    assert_eq!(keys.verify_keys[0].verify(&event), VerifyResult::Valid);
+
+    // Someone else's server can't be passed off as this one.
+    assert!(validate_fetched_key_response(&response, "example.org", HOUR, 12 * HOUR).is_err());
+}
+
+#[test]
+fn rotate() {
+    let mut ring = KeyRing::new("example.com");
+    ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:1")).unwrap();
+    let mut server = KeyServer::new(ring, 24 * HOUR);
+    let before = fetch(&mut server, 0);
+
+    let retired = server.ring_mut()
+                        .rotate_namespace(SigningKeyPair::generate("example.com", "ed25519:2"),
+                                          HOUR)
+                        .unwrap();
+    assert_eq!(retired, vec!["ed25519:1".to_string()]);
+
+    let after = fetch(&mut server, HOUR);
+    assert!(after != before);
+    let keys = validate_fetched_key_response(&after, "example.com", HOUR, 12 * HOUR).unwrap();
+    assert_eq!(keys.verify_keys[0].key_id, "ed25519:2");
+    assert_eq!(keys.old_verify_keys[0].0.key_id, "ed25519:1");
+    assert_eq!(keys.old_verify_keys[0].1, HOUR);
+}
+
+#[test]
+fn wrong_entity() {
+    let mut ring = KeyRing::new("example.com");
+    ring.add_signing_key(SigningKeyPair::generate("example.com", "ed25519:1")).unwrap();
+    let mut server = KeyServer::new(ring, 24 * HOUR);
+    let before = fetch(&mut server, 0);
+
+    let other = SigningKeyPair::generate("example.org", "ed25519:2");
+    assert_eq!(server.ring_mut().rotate_namespace(other, HOUR),
+               Err(KeyRingError::WrongEntity("example.org".to_string())));
+
+    // The served keys are unchanged, though re-signed.
+    let after = fetch(&mut server, HOUR);
+    let keys = validate_fetched_key_response(&after, "example.com", HOUR, 12 * HOUR).unwrap();
+    assert_eq!(keys.verify_keys.len(), 1);
+    assert_eq!(keys.verify_keys[0].key_id, "ed25519:1");
+    assert!(keys.old_verify_keys.is_empty());
+    assert!(after != before);
+}